@@ -0,0 +1,49 @@
+use bilibili_extractor_lib::{
+    danmaku::DanmakuConfig,
+    error::{Error, Result},
+    muxer::FFmpegMuxer,
+    subtitle::SsaStyleConfig,
+};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, fs::read_to_string, path::Path};
+
+/// Settings loaded from the JSON file given with `--config`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub ffmpeg: FFmpegMuxer,
+    /// Danmaku settings used with `--danmaku`.
+    pub danmaku: DanmakuConfig,
+    /// Subtitle styles selected with `--subtitle-style`.
+    pub subtitle_styles: BTreeMap<String, SsaStyleConfig>,
+}
+
+impl Config {
+    pub fn new_from_path(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let content = read_to_string(path).map_err(|e| {
+            Error::InputError(format!("Cannot read config {}: {e}", path.display()))
+        })?;
+
+        serde_json::from_str(&content)
+            .map_err(|e| Error::InputError(format!("Invalid config {}: {e}", path.display())))
+    }
+
+    /// Get a subtitle style by name. `default` is the built-in style unless the config file
+    /// overrides it.
+    pub fn get_subtitle_style(&self, name: &str) -> Result<SsaStyleConfig> {
+        match (self.subtitle_styles.get(name), name) {
+            (Some(s), _) => s.validate().map(|_| s.clone()),
+            (None, "default") => Ok(SsaStyleConfig::default()),
+            (None, _) => Err(Error::InputError(format!(
+                "Unknown subtitle style \"{name}\". Available styles: {}",
+                self.subtitle_styles
+                    .keys()
+                    .map(String::as_str)
+                    .chain((!self.subtitle_styles.contains_key("default")).then_some("default"))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ))),
+        }
+    }
+}
@@ -36,57 +36,103 @@ impl Lister {
                 .filter(|e| matches!(e.episode, EpisodeId::Special(_)))
                 .collect();
 
-            match (normal_episodes.is_empty(), special_episodes.is_empty()) {
-                (true, true) => println!("{}", "    No Episodes!!".color_as_error()),
-                (false, true) => {
+            let part_episodes: Vec<_> = s
+                .episodes
+                .iter()
+                .filter(|e| matches!(e.episode, EpisodeId::Part(_)))
+                .collect();
+
+            match (
+                normal_episodes.is_empty(),
+                special_episodes.is_empty(),
+                part_episodes.is_empty(),
+            ) {
+                (true, true, true) => println!("{}", "    No Episodes!!".color_as_error()),
+                (false, true, true) => {
                     println!("{}", "    Episodes:".color_as_success());
-                    self.list_normal_episodes(normal_episodes);
+                    self.list_episodes(normal_episodes);
                 }
-                (true, false) => {
+                (true, false, true) => {
                     println!("{}", "    Episodes:".color_as_success());
-                    self.list_special_episodes(special_episodes);
+                    self.list_episodes(special_episodes);
+                }
+                (true, true, false) => {
+                    println!("{}", "    Parts:".color_as_success());
+                    self.list_episodes(part_episodes);
                 }
                 _ => {
-                    println!("{}", "    Normal Episodes:\n".color_as_success());
-                    self.list_normal_episodes(normal_episodes);
-                    println!("{}", "    Special Episodes:\n".color_as_success());
-                    self.list_special_episodes(special_episodes);
+                    if !normal_episodes.is_empty() {
+                        println!("{}", "    Normal Episodes:\n".color_as_success());
+                        self.list_episodes(normal_episodes);
+                    }
+                    if !special_episodes.is_empty() {
+                        println!("{}", "    Special Episodes:\n".color_as_success());
+                        self.list_episodes(special_episodes);
+                    }
+                    if !part_episodes.is_empty() {
+                        println!("{}", "    Parts:\n".color_as_success());
+                        self.list_episodes(part_episodes);
+                    }
                 }
             }
         })
     }
 
-    pub fn list_normal_episodes(&self, episodes: Vec<&EpisodeMetadata>) {
-        #[cfg(not(debug_assertions))]
-        episodes
-            .iter()
-            .for_each(|e| println!("        {} {}", e.title, e.episode.get_full_display()));
-
-        #[cfg(debug_assertions)]
+    pub fn list_episodes(&self, episodes: Vec<&EpisodeMetadata>) {
         episodes.iter().for_each(|e| {
+            #[cfg(not(debug_assertions))]
+            match &e.episode_title {
+                Some(t) => println!(
+                    "        {} {} - {}",
+                    e.title,
+                    e.episode.get_full_display(),
+                    t
+                ),
+                None => println!("        {} {}", e.title, e.episode.get_full_display()),
+            }
+
+            #[cfg(debug_assertions)]
             println!(
-                "        {} {}, Path: {:?}",
+                "        {} {}, Episode Title: {:?}, Path: {:?}",
                 e.title,
                 e.episode.get_full_display(),
+                e.episode_title,
                 e.path
-            )
+            );
+
+            self.list_download_status(e);
+            self.list_subtitle_languages(e);
         });
     }
 
-    pub fn list_special_episodes(&self, episodes: Vec<&EpisodeMetadata>) {
-        #[cfg(not(debug_assertions))]
-        episodes
-            .iter()
-            .for_each(|e| println!("        {} {}", e.title, e.episode.get_full_display()));
+    pub fn list_download_status(&self, episode: &EpisodeMetadata) {
+        if !episode.download_status.is_incomplete() {
+            return;
+        }
 
-        #[cfg(debug_assertions)]
-        episodes.iter().for_each(|e| {
-            println!(
-                "        {} {}, Path: {:?}",
-                e.title,
-                e.episode.get_full_display(),
-                e.path
-            )
-        });
+        let message = match episode.download_status.get_percentage() {
+            Some(p) => format!("Incomplete Download!! ({p:.0}%)"),
+            None => "Incomplete Download!!".into(),
+        };
+
+        println!("            {}", message.color_as_warning())
+    }
+
+    pub fn list_subtitle_languages(&self, episode: &EpisodeMetadata) {
+        match episode.get_subtitle_languages() {
+            Ok(l) if l.is_empty() => {
+                println!("            {}", "No Subtitles!!".color_as_warning())
+            }
+            Ok(l) => println!(
+                "            {} {}",
+                "Subtitles:".color_as_success(),
+                l.join(", ")
+            ),
+            Err(e) => println!(
+                "            {} {}",
+                "Subtitles:".color_as_error(),
+                e.to_string().color_as_error()
+            ),
+        }
     }
 }
@@ -1,29 +1,41 @@
 use crate::colorer::Colorer;
 use bilibili_extractor_lib::{
-    error::Result,
+    compiler::{Compiler, CompilerConfig},
+    danmaku::{DanmakuConfig, DanmakuMode},
+    error::{Error, Result},
+    font::FontConfig,
     metadata::DownloadFolder,
+    muxer::{EncoderConfig, FFmpegMuxer, NativeMuxer, VideoCodec, VideoQuality},
     packager::{Packager, PackagerConfig},
-    subtitle::SubtitleType,
+    report::CompileReport,
+    subtitle::{MissingSubtitlePolicy, SsaStyleConfig, SubtitleType},
 };
-use clap::{Parser, Subcommand};
-use compiler::Compiler;
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use config::Config;
 use lister::Lister;
-use spinners::Spinner;
+use progress::ProgressDisplay;
+use regex::Regex;
+use reporter::Reporter;
+use std::process::ExitCode;
 
 mod colorer;
-mod compiler;
+mod config;
 mod lister;
+mod progress;
+mod reporter;
 
 #[derive(Debug, Clone, Default)]
 pub struct Context {
-    pub language: String,
-    pub subtitle_type: SubtitleType,
+    pub compiler_config: CompilerConfig,
     pub packager: Packager,
+    pub muxer: FFmpegMuxer,
+    pub use_native_muxer: bool,
     pub input_path: String,
-    pub is_parallel: bool,
+    pub report_path: Option<String>,
 }
 
 #[derive(Parser)]
+#[command(after_help = EXIT_CODES_HELP)]
 struct Cli {
     #[command(subcommand)]
     subcommand: SubCommands,
@@ -38,25 +50,382 @@ enum SubCommands {
     },
 
     #[command(about = "Compile all seasons in the input directory to the output directory.")]
-    Compile {
-        #[arg(help = "The input directory.")]
-        input: String,
+    Compile(Box<CompileArgs>),
+}
 
-        #[arg(help = "The output direcctory.")]
-        output: String,
+#[derive(Args)]
+struct CompileArgs {
+    #[arg(help = "The input directory.")]
+    input: String,
 
-        #[clap(long, short, help = "Copy combined files.")]
-        copy: bool,
+    #[arg(help = "The output direcctory.")]
+    output: String,
 
-        #[clap(long, short, help = "Set language for the subtitle.", default_value_t = String::from("en"))]
-        language: String,
+    #[clap(long, short, help = "Copy combined files.")]
+    copy: bool,
 
-        #[clap(long, help = "Set language for the subtitle.")]
-        use_hard_subtitle: bool,
+    #[clap(long, short, help = "Set language for the subtitle.", default_value_t = String::from("en"))]
+    language: String,
 
-        #[clap(long, short, help = "Compile episodes in parallel.")]
-        parallel: bool,
-    },
+    #[clap(
+        long,
+        short,
+        help = "Mux every available subtitle language. `--language` sets the default track."
+    )]
+    all_languages: bool,
+
+    #[clap(
+        long,
+        value_enum,
+        help = "What to do with episodes without subtitles.",
+        default_value_t = MissingSubtitle::Require
+    )]
+    missing_subtitle: MissingSubtitle,
+
+    #[clap(long, help = "Set language for the subtitle.")]
+    use_hard_subtitle: bool,
+
+    #[command(flatten)]
+    encoder: EncoderArgs,
+
+    #[clap(
+        long,
+        value_name = "DIR",
+        conflicts_with = "use_hard_subtitle",
+        help = "Attach every font in this directory. Subtitle styles using a font that isn't attached use the first one."
+    )]
+    font_dir: Option<String>,
+
+    #[clap(
+        long = "font",
+        value_name = "FILE",
+        conflicts_with = "use_hard_subtitle",
+        help = "Attach this font. Can be repeated."
+    )]
+    font_files: Vec<String>,
+
+    #[clap(
+        long,
+        value_name = "NAME",
+        default_value_t = String::from("default"),
+        help = "Style of subtitles converted from JSON, from `subtitle_styles` in the config file."
+    )]
+    subtitle_style: String,
+
+    #[clap(
+        long,
+        value_name = "FILE",
+        conflicts_with = "subtitle_style",
+        help = "Read the style of subtitles converted from JSON from this JSON file."
+    )]
+    subtitle_style_file: Option<String>,
+
+    #[clap(
+        long,
+        conflicts_with = "use_hard_subtitle",
+        help = "Mux the danmaku of each episode as an extra subtitle track."
+    )]
+    danmaku: bool,
+
+    #[command(flatten)]
+    danmaku_args: DanmakuArgs,
+
+    #[clap(
+        long,
+        help = "Write MKV files without ffmpeg. Doesn't support hard subtitles.",
+        conflicts_with = "use_hard_subtitle"
+    )]
+    native_muxer: bool,
+
+    #[clap(
+        long,
+        env = "BILIBILI_EXTRACTOR_CONFIG",
+        help = "Read settings from this JSON file."
+    )]
+    config: Option<String>,
+
+    #[clap(
+        long,
+        env = "BILIBILI_EXTRACTOR_FFMPEG",
+        help = "Path of the ffmpeg executable. Overrides the config file."
+    )]
+    ffmpeg: Option<String>,
+
+    #[clap(
+        long,
+        value_name = "ARG",
+        allow_hyphen_values = true,
+        help = "Extra ffmpeg argument placed before every input. Can be repeated."
+    )]
+    ffmpeg_input_arg: Vec<String>,
+
+    #[clap(
+        long,
+        value_name = "ARG",
+        allow_hyphen_values = true,
+        help = "Extra ffmpeg argument placed before the output file. Can be repeated."
+    )]
+    ffmpeg_output_arg: Vec<String>,
+
+    #[clap(long, short, help = "Compile episodes in parallel.")]
+    parallel: bool,
+
+    #[clap(
+        long,
+        short,
+        help = "Keep compiling when an episode fails and report failures at the end."
+    )]
+    keep_going: bool,
+
+    #[clap(
+        long,
+        help = "Compile episodes whose download didn't finish instead of skipping them."
+    )]
+    include_incomplete: bool,
+
+    #[clap(
+        long,
+        help = "Write a JSON report of the compiled episodes to this file."
+    )]
+    report: Option<String>,
+}
+
+/// Encoder options for `--use-hard-subtitle`. Unset options keep the value from the config file.
+#[derive(Args)]
+struct EncoderArgs {
+    #[clap(
+        long,
+        value_enum,
+        requires = "use_hard_subtitle",
+        help = "Video encoder used to burn hard subtitles."
+    )]
+    video_codec: Option<Codec>,
+
+    #[clap(
+        long,
+        requires = "use_hard_subtitle",
+        conflicts_with = "video_bitrate",
+        help = "Constant rate factor of the video encoder."
+    )]
+    crf: Option<u8>,
+
+    #[clap(
+        long,
+        value_name = "BITRATE",
+        requires = "use_hard_subtitle",
+        help = "Target bitrate of the video encoder, e.g. `4M`."
+    )]
+    video_bitrate: Option<String>,
+
+    #[clap(
+        long,
+        requires = "use_hard_subtitle",
+        help = "Preset of the video encoder, e.g. `slow`."
+    )]
+    preset: Option<String>,
+
+    #[clap(
+        long,
+        requires = "use_hard_subtitle",
+        help = "Pixel format of the video, e.g. `yuv420p`."
+    )]
+    pixel_format: Option<String>,
+
+    #[clap(
+        long,
+        requires = "use_hard_subtitle",
+        help = "Re-encode the audio with this codec, e.g. `aac`. Use `copy` to copy it."
+    )]
+    audio_codec: Option<String>,
+
+    #[clap(
+        long,
+        value_name = "BITRATE",
+        requires = "audio_codec",
+        help = "Bitrate of the re-encoded audio, e.g. `192k`."
+    )]
+    audio_bitrate: Option<String>,
+}
+
+impl EncoderArgs {
+    fn apply(self, config: &mut EncoderConfig) {
+        if let Some(video_codec) = self.video_codec {
+            config.video_codec = video_codec.into();
+        }
+        if let Some(crf) = self.crf {
+            config.quality = Some(VideoQuality::Crf(crf));
+        }
+        if let Some(video_bitrate) = self.video_bitrate {
+            config.quality = Some(VideoQuality::Bitrate(video_bitrate));
+        }
+        if let Some(preset) = self.preset {
+            config.preset = Some(preset);
+        }
+        if let Some(pixel_format) = self.pixel_format {
+            config.pixel_format = Some(pixel_format);
+        }
+        if let Some(audio_codec) = self.audio_codec {
+            config.audio_codec = Some(audio_codec).filter(|c| c != "copy");
+        }
+        if let Some(audio_bitrate) = self.audio_bitrate {
+            config.audio_bitrate = Some(audio_bitrate);
+        }
+    }
+}
+
+/// Danmaku filters for `--danmaku`. Unset options keep the value from the config file.
+#[derive(Args)]
+struct DanmakuArgs {
+    #[clap(
+        long = "danmaku-block",
+        value_name = "KEYWORD",
+        requires = "danmaku",
+        help = "Drop danmaku containing this keyword. Can be repeated."
+    )]
+    blocked_keywords: Vec<String>,
+
+    #[clap(
+        long = "danmaku-block-pattern",
+        value_name = "REGEX",
+        requires = "danmaku",
+        value_parser = parse_pattern,
+        help = "Drop danmaku matching this regular expression. Can be repeated."
+    )]
+    blocked_patterns: Vec<String>,
+
+    #[clap(
+        long = "danmaku-mode",
+        value_enum,
+        value_delimiter = ',',
+        requires = "danmaku",
+        help = "Only show danmaku of these modes."
+    )]
+    modes: Vec<DanmakuKind>,
+
+    #[clap(
+        long = "danmaku-max-on-screen",
+        value_name = "COUNT",
+        requires = "danmaku",
+        help = "Most danmaku shown at the same time."
+    )]
+    max_on_screen: Option<usize>,
+
+    #[clap(
+        long = "danmaku-min-font-size",
+        value_name = "SIZE",
+        requires = "danmaku",
+        help = "Drop danmaku smaller than this Bilibili font size, e.g. `25` drops small danmaku."
+    )]
+    min_font_size: Option<u32>,
+
+    #[clap(
+        long = "danmaku-opacity",
+        value_name = "OPACITY",
+        requires = "danmaku",
+        help = "Opacity of the danmaku, from 0 to 1."
+    )]
+    opacity: Option<f32>,
+
+    #[clap(
+        long = "danmaku-scroll-duration",
+        value_name = "SECONDS",
+        requires = "danmaku",
+        help = "Seconds a scrolling danmaku takes to cross the screen."
+    )]
+    scroll_duration: Option<f32>,
+
+    #[clap(
+        long = "danmaku-dedup",
+        value_name = "SECONDS",
+        requires = "danmaku",
+        help = "Drop danmaku repeating one shown less than this many seconds before."
+    )]
+    dedup_window: Option<f32>,
+}
+
+impl DanmakuArgs {
+    fn apply(self, config: &mut DanmakuConfig) {
+        config.blocked_keywords.extend(self.blocked_keywords);
+        config.blocked_patterns.extend(self.blocked_patterns);
+
+        if !self.modes.is_empty() {
+            config.modes = self.modes.into_iter().map(Into::into).collect();
+        }
+        if let Some(max_on_screen) = self.max_on_screen {
+            config.max_on_screen = Some(max_on_screen);
+        }
+        if let Some(min_font_size) = self.min_font_size {
+            config.min_font_size = min_font_size;
+        }
+        if let Some(opacity) = self.opacity {
+            config.opacity = opacity;
+        }
+        if let Some(scroll_duration) = self.scroll_duration {
+            config.scroll_duration = scroll_duration;
+        }
+        if let Some(dedup_window) = self.dedup_window {
+            config.dedup_window = Some(dedup_window);
+        }
+    }
+}
+
+/// Check that a blocked pattern is a valid regular expression.
+fn parse_pattern(pattern: &str) -> std::result::Result<String, regex::Error> {
+    Regex::new(pattern).map(|_| pattern.into())
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum DanmakuKind {
+    Scroll,
+    Top,
+    Bottom,
+}
+
+impl From<DanmakuKind> for DanmakuMode {
+    fn from(value: DanmakuKind) -> Self {
+        match value {
+            DanmakuKind::Scroll => DanmakuMode::Scroll,
+            DanmakuKind::Top => DanmakuMode::Top,
+            DanmakuKind::Bottom => DanmakuMode::Bottom,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Codec {
+    X264,
+    X265,
+    Av1,
+}
+
+impl From<Codec> for VideoCodec {
+    fn from(value: Codec) -> Self {
+        match value {
+            Codec::X264 => VideoCodec::X264,
+            Codec::X265 => VideoCodec::X265,
+            Codec::Av1 => VideoCodec::Av1,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum MissingSubtitle {
+    /// Fail the episode.
+    Require,
+    /// Skip the episode.
+    Skip,
+    /// Combine the episode without subtitles.
+    Mux,
+}
+
+impl From<MissingSubtitle> for MissingSubtitlePolicy {
+    fn from(value: MissingSubtitle) -> Self {
+        match value {
+            MissingSubtitle::Require => MissingSubtitlePolicy::Require,
+            MissingSubtitle::Skip => MissingSubtitlePolicy::Skip,
+            MissingSubtitle::Mux => MissingSubtitlePolicy::MuxWithout,
+        }
+    }
 }
 
 fn list(context: Context) -> Result<()> {
@@ -65,23 +434,93 @@ fn list(context: Context) -> Result<()> {
 
     lister.list_seasons(&download_directory.seasons);
 
+    let invalid_episodes: Vec<_> = download_directory.get_invalid_episodes().cloned().collect();
+    if !invalid_episodes.is_empty() {
+        eprintln!("\n{}", "Unreadable Episodes:".color_as_error());
+        Reporter.list_invalid_episodes(&invalid_episodes);
+    }
+
     Ok(())
 }
 
-fn compile(context: Context) -> Result<()> {
-    let download_directory = DownloadFolder::new_from_path(context.input_path.clone())?;
-    let compiler = Compiler::new(context);
+fn compile(context: Context) -> Result<CompileReport> {
+    let download_directory = DownloadFolder::new_from_path(context.input_path)?;
+    let keep_going = context.compiler_config.keep_going;
+    let compiler = Compiler::new(context.packager)
+        .set_config(context.compiler_config)
+        .set_event_handler(ProgressDisplay::default());
+    let compiler = match context.use_native_muxer {
+        true => compiler.set_muxer(NativeMuxer),
+        false => compiler.set_muxer(context.muxer),
+    };
 
-    compiler.compile_seasons(&download_directory.seasons)?;
+    let mut report = CompileReport::default();
+    let result = compiler.compile_with_report(&download_directory, &mut report);
 
-    Ok(())
+    // Skipped and failed episodes are easy to miss between the progress of the others.
+    if keep_going || report.skipped().next().is_some() || report.has_failures() {
+        Reporter.print_report(&report);
+    }
+
+    // The report is also written if compiling stopped at an error.
+    let saved = match context.report_path {
+        Some(report_path) => report.save_json(report_path),
+        None => Ok(()),
+    };
+    result.and(saved)?;
+
+    Ok(report)
+}
+
+/// Read the config file, or use the default config if there is none.
+fn get_config(config_path: Option<String>) -> Result<Config> {
+    match config_path {
+        Some(p) => Config::new_from_path(p),
+        None => Ok(Config::default()),
+    }
+}
+
+/// Get the `ffmpeg` muxer from the config file, then apply the command line overrides.
+fn get_ffmpeg_muxer(
+    muxer: FFmpegMuxer,
+    ffmpeg_path: Option<String>,
+    input_args: Vec<String>,
+    output_args: Vec<String>,
+    encoder_args: EncoderArgs,
+) -> FFmpegMuxer {
+    let mut muxer = muxer
+        .with_input_args(input_args)
+        .with_output_args(output_args);
+
+    if let Some(ffmpeg_path) = ffmpeg_path {
+        muxer.ffmpeg_path = ffmpeg_path.into();
+    }
+    encoder_args.apply(&mut muxer.encoder);
+
+    muxer
 }
 
-pub fn create_spinner(message: &str) -> Spinner {
-    Spinner::new(spinners::Spinners::Dots, message.into())
+/// Get the process exit code of an error. See `EXIT_CODES_HELP`.
+fn get_exit_code(error: &Error) -> u8 {
+    match error {
+        Error::InputError(_) => 3,
+        Error::FFmpegError { .. } => 4,
+        Error::SubtitleError(_) => 5,
+        Error::PackagingError(_) => 6,
+        _ => 1,
+    }
 }
 
-fn main() {
+const EXIT_CODES_HELP: &str = "Exit codes:
+  0  Success.
+  1  Other errors, or some episodes failed with `--keep-going`.
+  2  Invalid command line arguments.
+  3  The input directory is invalid.
+  4  ffmpeg failed.
+  5  A subtitle could not be parsed or converted.
+  6  A compiled episode could not be packaged.";
+
+fn main() -> ExitCode {
     #[cfg(debug_assertions)]
     {
         println!("{}", "Debug Build!".color_as_warning());
@@ -90,7 +529,7 @@ fn main() {
 
     let cli = Cli::parse();
 
-    match cli.subcommand {
+    let result = match cli.subcommand {
         SubCommands::List { input } => {
             let context = Context {
                 input_path: input,
@@ -104,39 +543,98 @@ fn main() {
                 context
             );
 
-            let _ = list(context).inspect_err(|e| println!("{}", e.to_string().color_as_error()));
+            list(context).map(|_| ExitCode::SUCCESS)
         }
-        SubCommands::Compile {
-            input,
-            output,
-            copy,
-            language,
-            use_hard_subtitle,
-            parallel,
-        } => {
-            let context = Context {
+        SubCommands::Compile(args) => {
+            let CompileArgs {
+                input,
+                output,
+                copy,
                 language,
-                subtitle_type: match use_hard_subtitle {
-                    true => SubtitleType::Hard,
-                    false => SubtitleType::Soft,
-                },
-                packager: Packager {
-                    output_path: output.into(),
-                    config: PackagerConfig { copy },
-                },
-                input_path: input,
-                is_parallel: parallel,
-            };
+                all_languages,
+                missing_subtitle,
+                use_hard_subtitle,
+                encoder,
+                font_dir,
+                font_files,
+                subtitle_style,
+                subtitle_style_file,
+                danmaku,
+                danmaku_args,
+                native_muxer,
+                config,
+                ffmpeg,
+                ffmpeg_input_arg,
+                ffmpeg_output_arg,
+                parallel,
+                keep_going,
+                include_incomplete,
+                report,
+            } = *args;
 
-            #[cfg(debug_assertions)]
-            println!(
-                "{} Compile Context: {:?}\n",
-                "DEBUG:".color_as_warning(),
-                context
-            );
+            get_config(config).and_then(|config| {
+                let subtitle_style = match subtitle_style_file {
+                    Some(p) => SsaStyleConfig::new_from_path(p)?,
+                    None => config.get_subtitle_style(&subtitle_style)?,
+                };
+                let mut danmaku_config = config.danmaku;
+                danmaku_args.apply(&mut danmaku_config);
 
-            let _ =
-                compile(context).inspect_err(|e| println!("{}", e.to_string().color_as_error()));
+                let muxer = get_ffmpeg_muxer(
+                    config.ffmpeg,
+                    ffmpeg,
+                    ffmpeg_input_arg,
+                    ffmpeg_output_arg,
+                    encoder,
+                );
+
+                let context = Context {
+                    compiler_config: CompilerConfig {
+                        language,
+                        all_languages,
+                        missing_subtitle_policy: missing_subtitle.into(),
+                        subtitle_type: match use_hard_subtitle {
+                            true => SubtitleType::Hard,
+                            false => SubtitleType::Soft,
+                        },
+                        is_parallel: parallel,
+                        keep_going,
+                        fonts: FontConfig {
+                            font_dir: font_dir.map(Into::into),
+                            font_files: font_files.into_iter().map(Into::into).collect(),
+                        },
+                        subtitle_style,
+                        danmaku: danmaku.then_some(danmaku_config),
+                        include_incomplete,
+                    },
+                    packager: Packager {
+                        output_path: output.into(),
+                        config: PackagerConfig { copy },
+                    },
+                    muxer,
+                    use_native_muxer: native_muxer,
+                    input_path: input,
+                    report_path: report,
+                };
+
+                #[cfg(debug_assertions)]
+                println!(
+                    "{} Compile Context: {:?}\n",
+                    "DEBUG:".color_as_warning(),
+                    context
+                );
+
+                compile(context).map(|r| match r.has_failures() {
+                    true => ExitCode::FAILURE,
+                    false => ExitCode::SUCCESS,
+                })
+            })
         }
-    }
+    };
+
+    result.unwrap_or_else(|e| {
+        eprintln!("{}", e.to_string().color_as_error());
+
+        ExitCode::from(get_exit_code(&e))
+    })
 }
@@ -0,0 +1,219 @@
+use crate::colorer::Colorer;
+use bilibili_extractor_lib::{
+    combiner::SubtitleTrack, event::EventHandler, metadata::EpisodeMetadata, muxer::MuxProgress,
+    report::EpisodeStatus,
+};
+use std::{
+    io::{stdout, IsTerminal, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::Duration,
+};
+
+const SPINNER_FRAMES: [&str; 10] = ["⠋", "⠙", "⠹", "⠸", "⠼", "⠴", "⠦", "⠧", "⠇", "⠏"];
+
+/// Shows one row for every episode being compiled. Finished rows are printed above the active
+/// ones. If stdout isn't a terminal, e.g. when it's redirected to a log, every message is printed
+/// as a plain line instead and mux progress is left out.
+#[derive(Debug)]
+pub struct ProgressDisplay {
+    state: Mutex<ProgressState>,
+    is_terminal: bool,
+}
+
+#[derive(Debug, Default)]
+struct ProgressState {
+    rows: Vec<ProgressRow>,
+    /// Number of lines drawn the last time, which get cleared before drawing again.
+    drawn_lines: usize,
+}
+
+#[derive(Debug)]
+struct ProgressRow {
+    /// Path of the episode.
+    id: PathBuf,
+    message: String,
+    frame: usize,
+}
+
+impl Default for ProgressDisplay {
+    fn default() -> Self {
+        Self {
+            state: Default::default(),
+            is_terminal: stdout().is_terminal(),
+        }
+    }
+}
+
+impl ProgressDisplay {
+    pub fn add_row(&self, id: PathBuf, message: String) {
+        let mut state = self.state.lock().unwrap();
+
+        if !self.is_terminal {
+            return print_line(&message);
+        }
+
+        state.rows.push(ProgressRow {
+            id,
+            message,
+            frame: 0,
+        });
+        draw(&mut state, None);
+    }
+
+    pub fn update_row(&self, id: &Path, message: String) {
+        let mut state = self.state.lock().unwrap();
+
+        if !self.is_terminal {
+            return print_line(&message);
+        }
+
+        if let Some(row) = state.rows.iter_mut().find(|r| r.id == *id) {
+            row.message = message;
+            row.frame = (row.frame + 1) % SPINNER_FRAMES.len();
+        }
+        draw(&mut state, None);
+    }
+
+    /// Remove a row and print `message` in its place.
+    pub fn finish_row(&self, id: &Path, message: String) {
+        let mut state = self.state.lock().unwrap();
+
+        if !self.is_terminal {
+            return print_line(&message);
+        }
+
+        state.rows.retain(|r| r.id != *id);
+        draw(&mut state, Some(message));
+    }
+}
+
+impl EventHandler for ProgressDisplay {
+    fn on_episode_started(&self, episode: &EpisodeMetadata) {
+        self.add_row(
+            episode.path.clone(),
+            format!("Compiling {}...", get_episode_name(episode)),
+        )
+    }
+
+    fn on_subtitle_converted(&self, episode: &EpisodeMetadata, track: &SubtitleTrack) {
+        self.update_row(
+            &episode.path,
+            format!(
+                "{}: Converted {} subtitle",
+                get_episode_name(episode),
+                track.get_title()
+            ),
+        )
+    }
+
+    fn on_mux_started(&self, episode: &EpisodeMetadata) {
+        self.update_row(
+            &episode.path,
+            format!("{}: Muxing...", get_episode_name(episode)),
+        )
+    }
+
+    fn on_mux_progress(&self, episode: &EpisodeMetadata, progress: &MuxProgress) {
+        // Progress would fill logs with a line for every update.
+        if !self.is_terminal {
+            return;
+        }
+
+        self.update_row(
+            &episode.path,
+            format!(
+                "{}: Muxing {}",
+                get_episode_name(episode),
+                format_progress(progress)
+            ),
+        )
+    }
+
+    fn on_episode_finished(&self, episode: &EpisodeMetadata, status: &EpisodeStatus) {
+        let name = get_episode_name(episode);
+        let message = match status {
+            EpisodeStatus::Succeeded => format!(
+                "{} {}",
+                "✔".color_as_success(),
+                format!("Compiled {name}!").color_as_success()
+            ),
+            EpisodeStatus::Skipped(r) => format!(
+                "{} {}",
+                "⚠".color_as_warning(),
+                format!("Skipped {name}: {r}").color_as_warning()
+            ),
+            EpisodeStatus::Failed(_) => format!(
+                "{} {}",
+                "✘".color_as_error(),
+                format!("Failed to compile {name}!").color_as_error()
+            ),
+        };
+
+        self.finish_row(&episode.path, message)
+    }
+}
+
+fn get_episode_name(episode: &EpisodeMetadata) -> String {
+    format!("{} {}", episode.title, episode.episode.get_short_display())
+}
+
+/// Print a message without moving the cursor.
+fn print_line(message: &str) {
+    let mut stdout = stdout().lock();
+
+    let _ = writeln!(stdout, "{message}");
+    let _ = stdout.flush();
+}
+
+fn draw(state: &mut ProgressState, finished_message: Option<String>) {
+    let mut stdout = stdout().lock();
+
+    // Move to the first drawn line and clear everything below it.
+    if state.drawn_lines > 0 {
+        let _ = write!(stdout, "\x1b[{}A", state.drawn_lines);
+    }
+    let _ = write!(stdout, "\r\x1b[J");
+
+    if let Some(m) = finished_message {
+        let _ = writeln!(stdout, "{m}");
+    }
+
+    state.rows.iter().for_each(|r| {
+        let _ = writeln!(stdout, "{} {}", SPINNER_FRAMES[r.frame], r.message);
+    });
+    state.drawn_lines = state.rows.len();
+
+    let _ = stdout.flush();
+}
+
+/// Format the progress as e.g. `42% (1.5x, ETA 00:12)`.
+fn format_progress(progress: &MuxProgress) -> String {
+    let mut details = vec![];
+
+    if let Some(s) = progress.speed {
+        details.push(format!("{s:.1}x"));
+    }
+    if let Some(e) = progress.get_eta() {
+        details.push(format!("ETA {}", format_duration(e)));
+    }
+
+    let amount = match progress.get_percentage() {
+        Some(p) => format!("{p:.0}%"),
+        None => format_duration(progress.out_time),
+    };
+
+    match details.is_empty() {
+        true => amount,
+        false => format!("{amount} ({})", details.join(", ")),
+    }
+}
+
+fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+
+    match seconds / 3600 {
+        0 => format!("{:02}:{:02}", seconds / 60, seconds % 60),
+        h => format!("{h}:{:02}:{:02}", seconds / 60 % 60, seconds % 60),
+    }
+}
@@ -0,0 +1,65 @@
+use crate::colorer::Colorer;
+use bilibili_extractor_lib::{
+    metadata::InvalidEpisode,
+    report::{CompileReport, EpisodeReport, EpisodeStatus},
+};
+
+pub struct Reporter;
+
+impl Reporter {
+    pub fn print_report(&self, report: &CompileReport) {
+        println!(
+            "\n{} {} succeeded, {} skipped, {} failed.",
+            "Summary:".color_as_success(),
+            report.succeeded().count(),
+            report.skipped().count(),
+            report.failed().count()
+        );
+
+        let skipped: Vec<_> = report.skipped().collect();
+        if !skipped.is_empty() {
+            println!("{}", "    Skipped:".color_as_warning());
+            skipped
+                .iter()
+                .for_each(|e| println!("{}", self.format_episode_report(e)));
+        }
+
+        // Failures are errors, so they go to stderr with the other errors.
+        let failed: Vec<_> = report.failed().collect();
+        if !failed.is_empty() {
+            eprintln!("{}", "    Failed:".color_as_error());
+            failed
+                .iter()
+                .for_each(|e| eprintln!("{}", self.format_episode_report(e)));
+        }
+
+        if !report.invalid_episodes.is_empty() {
+            eprintln!("{}", "    Unreadable:".color_as_error());
+            self.list_invalid_episodes(&report.invalid_episodes);
+        }
+    }
+
+    pub fn list_invalid_episodes(&self, invalid_episodes: &[InvalidEpisode]) {
+        invalid_episodes.iter().for_each(|e| {
+            eprintln!(
+                "        {}: {}",
+                e.path.display(),
+                e.reason.replace('\n', "\n            ")
+            )
+        });
+    }
+
+    pub fn format_episode_report(&self, episode_report: &EpisodeReport) -> String {
+        let reason = match &episode_report.status {
+            EpisodeStatus::Skipped(r) | EpisodeStatus::Failed(r) => r.as_str(),
+            EpisodeStatus::Succeeded => "",
+        };
+
+        format!(
+            "        {} {}: {}",
+            episode_report.episode.title,
+            episode_report.episode.get_episode_display(),
+            reason.replace('\n', "\n            ")
+        )
+    }
+}
@@ -1,10 +1,22 @@
-use crate::{error::Result, metadata::EpisodeMetadata, subtitle::SubtitleType};
+use crate::{
+    error::Result,
+    font::get_font_family_name,
+    metadata::{EpisodeMetadata, MediaLayout},
+    muxer::{FFmpegMuxer, MuxJob, MuxProgress, Muxer},
+    subtitle::{get_language_name, set_ssa_font_name, SubtitleType},
+};
+use serde::{Deserialize, Serialize};
 use std::{
-    ffi::OsStr,
-    path::{PathBuf},
-    process::{Command, ExitStatus},
+    fs::{self, File},
+    io::{copy, BufWriter, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
 };
 
+/// Byte the Bilibili PC client pads the start of its `.m4s` files with.
+const M4S_PADDING_BYTE: u8 = b'0';
+/// Number of bytes read when looking for the padding.
+const M4S_PADDING_SEARCH_LENGTH: usize = 256;
+
 macro_rules! get_file {
     ($file_name: literal, $episode_ident: ident, $combinable: expr) => {
         $combinable
@@ -14,6 +26,29 @@ macro_rules! get_file {
     };
 }
 
+/// A subtitle to be combined with the audio and video as its own track.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+pub struct SubtitleTrack {
+    pub path: PathBuf,
+    /// Language directory name used by Bilibili, e.g. `en` or `zh-Hans`.
+    pub language: String,
+    /// Name of the track. Defaults to the name of the language.
+    #[serde(default)]
+    pub title: Option<String>,
+    /// Keep the fonts of the styles even if attached fonts are used by the other tracks.
+    #[serde(default)]
+    pub keep_fonts: bool,
+}
+
+impl SubtitleTrack {
+    /// Get the name of the track.
+    pub fn get_title(&self) -> &str {
+        self.title
+            .as_deref()
+            .unwrap_or_else(|| get_language_name(&self.language))
+    }
+}
+
 pub trait Combinable {
     /// Combine the audio, video, and subtitle using `ffmpeg`.
     fn combine(
@@ -21,47 +56,267 @@ pub trait Combinable {
         subtitle_path: impl Into<PathBuf>,
         subtitle_language: &str,
         subtitle_type: SubtitleType,
-    ) -> Result<ExitStatus>;
+    ) -> Result<()> {
+        self.combine_tracks(
+            &[SubtitleTrack {
+                path: subtitle_path.into(),
+                language: subtitle_language.into(),
+                title: None,
+                keep_fonts: false,
+            }],
+            0,
+            subtitle_type,
+        )
+    }
+
+    /// Combine only the audio and video using `ffmpeg`.
+    fn combine_without_subtitle(&self) -> Result<()> {
+        self.combine_tracks(&[], 0, SubtitleType::Soft)
+    }
+
+    /// Combine the audio, video, and every subtitle track using `ffmpeg`. `default_track` is the
+    /// index of the track marked as default. Hard subtitles only burn the default track.
+    ///
+    /// This and the methods above use the default `FFmpegMuxer`, so a failed `ffmpeg` is returned
+    /// as `Error::FFmpegError`.
+    fn combine_tracks(
+        &self,
+        subtitle_tracks: &[SubtitleTrack],
+        default_track: usize,
+        subtitle_type: SubtitleType,
+    ) -> Result<()> {
+        self.combine_with(
+            &FFmpegMuxer::default(),
+            subtitle_tracks,
+            default_track,
+            subtitle_type,
+        )
+    }
+
+    /// Combine the audio, video, and every subtitle track using the given `Muxer`.
+    fn combine_with(
+        &self,
+        muxer: &dyn Muxer,
+        subtitle_tracks: &[SubtitleTrack],
+        default_track: usize,
+        subtitle_type: SubtitleType,
+    ) -> Result<()> {
+        self.combine_with_progress(
+            muxer,
+            subtitle_tracks,
+            default_track,
+            subtitle_type,
+            &[],
+            &|_| {},
+        )
+    }
+
+    /// Same as `combine_with`, but calls `on_progress` while the `Muxer` runs. `font_files` are
+    /// attached to soft subtitles, and subtitle styles using a font that isn't attached are changed
+    /// to use the first one.
+    fn combine_with_progress(
+        &self,
+        muxer: &dyn Muxer,
+        subtitle_tracks: &[SubtitleTrack],
+        default_track: usize,
+        subtitle_type: SubtitleType,
+        font_files: &[PathBuf],
+        on_progress: &dyn Fn(&MuxProgress),
+    ) -> Result<()>;
 }
 
 impl Combinable for EpisodeMetadata {
-    fn combine(
+    fn combine_with_progress(
         &self,
-        subtitle_path: impl Into<PathBuf>,
-        subtitle_language: &str,
+        muxer: &dyn Muxer,
+        subtitle_tracks: &[SubtitleTrack],
+        default_track: usize,
         subtitle_type: SubtitleType,
-    ) -> Result<ExitStatus> {
-        let subtitle_path: PathBuf = subtitle_path.into();
-
-        let video_path = get_file!("video.m4s", episode, self);
-        let audio_path = get_file!("audio.m4s", episode, self);
-        let output_path = get_file!("episode.mkv", episode, self);
-
-        let mut binding = Command::new("ffmpeg");
-        binding
-            .arg("-y")
-            .args(["-hide_banner", "-loglevel", "error"]) // silent operation
-            .args([OsStr::new("-i"), video_path.as_os_str()])
-            .args([OsStr::new("-i"), audio_path.as_os_str()]);
-
-        match subtitle_type {
-            SubtitleType::Hard => Ok(binding
-                .args([
-                    "-vf",
-                    &format!("subtitles={}", subtitle_path.to_string_lossy()),
-                ])
-                .arg(output_path)
-                .status()?),
-            SubtitleType::Soft => Ok(binding
-                .args([OsStr::new("-i"), subtitle_path.as_os_str()])
-                .args(["-map", "0"])
-                .args(["-map", "1:a:0"])
-                .args(["-map", "2"])
-                .args(["-metadata:s:s:0", &format!("language={subtitle_language}")])
-                .args(["-disposition:s:s:0", "default"])
-                .args(["-codec", "copy"])
-                .arg(output_path)
-                .status()?),
+        font_files: &[PathBuf],
+        on_progress: &dyn Fn(&MuxProgress),
+    ) -> Result<()> {
+        // There is nothing to burn without subtitles, so just copy the streams.
+        let subtitle_type = match subtitle_tracks.is_empty() {
+            true => SubtitleType::Soft,
+            false => subtitle_type,
+        };
+
+        // Fonts are only attached to soft subtitles. Styles using a font that isn't attached are
+        // changed to the first one.
+        let attachments = match subtitle_type {
+            SubtitleType::Soft if !subtitle_tracks.is_empty() && !font_files.is_empty() => {
+                let font_names = font_files
+                    .iter()
+                    .map(get_font_family_name)
+                    .collect::<Result<Vec<_>>>()?;
+
+                subtitle_tracks
+                    .iter()
+                    .filter(|t| !t.keep_fonts)
+                    .try_for_each(|t| set_ssa_font_name(&t.path, &font_names[0], &font_names))?;
+
+                font_files.to_vec()
+            }
+            _ => vec![],
+        };
+
+        // Joined segments and stripped files are temporary, and are removed after muxing.
+        let mut temporary_paths = vec![];
+        let (video_path, audio_path) = match self.get_media_layout()? {
+            MediaLayout::Dash {
+                video_path,
+                audio_path,
+                video_padding,
+                audio_padding,
+            } => {
+                let mut strip =
+                    |path: PathBuf, padding: u64, stripped_path: PathBuf| -> Result<PathBuf> {
+                        if padding == 0 {
+                            return Ok(path);
+                        }
+
+                        temporary_paths.push(stripped_path.clone());
+                        strip_m4s_padding(&path, padding, &stripped_path)?;
+
+                        Ok(stripped_path)
+                    };
+
+                (
+                    strip(
+                        video_path,
+                        video_padding,
+                        get_file!("video.stripped.m4s", episode, self),
+                    )?,
+                    strip(
+                        audio_path,
+                        audio_padding,
+                        get_file!("audio.stripped.m4s", episode, self),
+                    )?,
+                )
+            }
+            // Segments contain both audio and video, so the same file is used for both.
+            MediaLayout::Segmented(segments) => {
+                let path = match segments.as_slice() {
+                    [segment] => segment.clone(),
+                    _ => {
+                        let path = get_file!("segments.mkv", episode, self);
+                        temporary_paths.push(path.clone());
+                        muxer.concat(&segments, &path)?;
+
+                        path
+                    }
+                };
+
+                (path.clone(), path)
+            }
+        };
+
+        let result = muxer.mux_with_progress(
+            &MuxJob {
+                video_path,
+                audio_path,
+                subtitle_tracks: subtitle_tracks.to_vec(),
+                default_track,
+                subtitle_type,
+                title: self.get_episode_display(),
+                output_path: get_file!("episode.mkv", episode, self),
+                duration: self.duration,
+                attachments,
+            },
+            on_progress,
+        );
+
+        for path in temporary_paths {
+            let _ = fs::remove_file(path);
         }
+
+        result
+    }
+}
+
+/// Get the length of the `0` padding the Bilibili PC client puts before the `ftyp` box of its
+/// `.m4s` files. Returns `0` if the file isn't padded.
+pub fn get_m4s_padding_length(path: impl AsRef<Path>) -> Result<u64> {
+    let mut buffer = Vec::with_capacity(M4S_PADDING_SEARCH_LENGTH);
+    File::open(path)?
+        .take(M4S_PADDING_SEARCH_LENGTH as u64)
+        .read_to_end(&mut buffer)?;
+
+    let length = buffer
+        .iter()
+        .take_while(|b| **b == M4S_PADDING_BYTE)
+        .count();
+
+    // The padding is followed by the size and type of the `ftyp` box.
+    match buffer.get(length + 4..length + 8) {
+        Some(b"ftyp") => Ok(length as u64),
+        _ => Ok(0),
+    }
+}
+
+/// Copy an `.m4s` file of the Bilibili PC client to `output_path` without its first
+/// `padding_length` bytes, found by [`get_m4s_padding_length`]. The file is streamed, so it's
+/// never fully loaded into memory.
+pub fn strip_m4s_padding(
+    path: impl AsRef<Path>,
+    padding_length: u64,
+    output_path: impl AsRef<Path>,
+) -> Result<()> {
+    let mut file = File::open(path)?;
+    file.seek(SeekFrom::Start(padding_length))?;
+
+    let mut writer = BufWriter::new(File::create(output_path)?);
+    copy(&mut file, &mut writer)?;
+    writer.flush()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mp4::tests::{get_temporary_path, mp4_box};
+
+    fn get_padding_length(name: &str, data: &[u8]) -> u64 {
+        let path = get_temporary_path(name);
+        fs::write(&path, data).unwrap();
+
+        let padding_length = get_m4s_padding_length(&path);
+        fs::remove_file(&path).unwrap();
+
+        padding_length.unwrap()
+    }
+
+    #[test]
+    fn padding_is_found_before_ftyp() {
+        let ftyp = mp4_box(b"ftyp", b"iso5\0\0\0\x01");
+        let padded = [b"000000000".as_slice(), &ftyp].concat();
+
+        assert_eq!(get_padding_length("padded.m4s", &padded), 9);
+        assert_eq!(get_padding_length("unpadded.m4s", &ftyp), 0);
+    }
+
+    #[test]
+    fn files_without_ftyp_are_not_padded() {
+        assert_eq!(get_padding_length("short.m4s", b"0ftyp"), 0);
+        assert_eq!(get_padding_length("empty.m4s", b""), 0);
+        assert_eq!(get_padding_length("zeros.m4s", &[b'0'; 1024]), 0);
+        assert_eq!(get_padding_length("zeros_short.m4s", b"0000000"), 0);
+    }
+
+    #[test]
+    fn padding_is_stripped() {
+        let ftyp = mp4_box(b"ftyp", b"iso5\0\0\0\x01");
+        let path = get_temporary_path("strip.m4s");
+        let output_path = get_temporary_path("strip.mp4");
+        fs::write(&path, [b"000".as_slice(), &ftyp].concat()).unwrap();
+
+        let result = strip_m4s_padding(&path, 3, &output_path);
+        let output = fs::read(&output_path);
+        fs::remove_file(&path).unwrap();
+        let _ = fs::remove_file(&output_path);
+
+        result.unwrap();
+        assert_eq!(output.unwrap(), ftyp);
     }
 }
@@ -0,0 +1,618 @@
+use crate::{
+    combiner::{Combinable, SubtitleTrack},
+    danmaku::{DanmakuConfig, DanmakuFile},
+    error::{Error, Result},
+    event::EventHandler,
+    font::FontConfig,
+    metadata::{DownloadFolder, EpisodeMetadata, SeasonMetadata},
+    muxer::{FFmpegMuxer, Muxer},
+    packager::Packager,
+    report::{CompileReport, EpisodeStatus},
+    subtitle::{JsonSubtitle, MissingSubtitlePolicy, SsaStyleConfig, SubtitleFormat, SubtitleType},
+};
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+use rsubs_lib::{srt::SRTFile, vtt::VTTFile};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{read_to_string, rename, write},
+    str::FromStr,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+/// Converts subtitles, combines, and packages every episode of a download folder.
+///
+/// # Compile a download folder
+///
+/// ```no_run
+/// use bilibili_extractor_lib::{
+///     compiler::{Compiler, CompilerConfig},
+///     metadata::DownloadFolder,
+///     packager::Packager,
+/// };
+///
+/// let download_folder = DownloadFolder::new_from_path("download").unwrap();
+/// let compiler = Compiler::new(Packager::new("output").unwrap()).set_config(CompilerConfig {
+///     all_languages: true,
+///     keep_going: true,
+///     ..Default::default()
+/// });
+///
+/// let report = compiler.compile(&download_folder).unwrap();
+///
+/// println!("{} episodes failed.", report.failed().count());
+/// ```
+pub struct Compiler {
+    pub config: CompilerConfig,
+    pub packager: Packager,
+    muxer: Box<dyn Muxer>,
+    events: Box<dyn EventHandler>,
+}
+
+/// Config used for compiling.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct CompilerConfig {
+    /// Language of the subtitle, or of the default track if `all_languages` is set.
+    pub language: String,
+    /// Mux every available subtitle language.
+    pub all_languages: bool,
+    pub missing_subtitle_policy: MissingSubtitlePolicy,
+    pub subtitle_type: SubtitleType,
+    /// Compile the episodes of a season in parallel.
+    pub is_parallel: bool,
+    /// Record failed episodes in the report instead of stopping at the first error.
+    pub keep_going: bool,
+    /// Fonts attached to soft subtitles.
+    pub fonts: FontConfig,
+    /// Style of subtitles converted from JSON.
+    pub subtitle_style: SsaStyleConfig,
+    /// Mux the danmaku of soft-subbed episodes as an extra subtitle track if set.
+    pub danmaku: Option<DanmakuConfig>,
+    /// Compile episodes whose download didn't finish instead of skipping them.
+    pub include_incomplete: bool,
+}
+
+impl Default for CompilerConfig {
+    fn default() -> Self {
+        Self {
+            language: "en".into(),
+            all_languages: false,
+            missing_subtitle_policy: Default::default(),
+            subtitle_type: Default::default(),
+            is_parallel: false,
+            keep_going: false,
+            fonts: Default::default(),
+            subtitle_style: Default::default(),
+            danmaku: None,
+            include_incomplete: false,
+        }
+    }
+}
+
+impl Compiler {
+    /// Create a new `Compiler` that uses `ffmpeg` and ignores events.
+    pub fn new(packager: Packager) -> Self {
+        Self {
+            config: Default::default(),
+            packager,
+            muxer: Box::new(FFmpegMuxer::default()),
+            events: Box::new(()),
+        }
+    }
+
+    /// Set config for `Compiler`.
+    pub fn set_config(self, config: CompilerConfig) -> Self {
+        Self { config, ..self }
+    }
+
+    /// Set the `Muxer` used to combine episodes.
+    pub fn set_muxer(self, muxer: impl Muxer + 'static) -> Self {
+        Self {
+            muxer: Box::new(muxer),
+            ..self
+        }
+    }
+
+    /// Set the `EventHandler` that receives events while compiling.
+    pub fn set_event_handler(self, events: impl EventHandler + 'static) -> Self {
+        Self {
+            events: Box::new(events),
+            ..self
+        }
+    }
+
+    /// Compile and package every season of the download folder. Episodes that couldn't be read
+    /// are recorded in the report.
+    pub fn compile(&self, download_folder: &DownloadFolder) -> Result<CompileReport> {
+        let mut report = CompileReport::default();
+        self.compile_with_report(download_folder, &mut report)?;
+
+        Ok(report)
+    }
+
+    /// Same as `compile`, but records into `report`, which keeps the episodes compiled before an
+    /// error.
+    pub fn compile_with_report(
+        &self,
+        download_folder: &DownloadFolder,
+        report: &mut CompileReport,
+    ) -> Result<()> {
+        report
+            .invalid_episodes
+            .extend(download_folder.invalid_episodes.iter().cloned());
+
+        download_folder
+            .seasons
+            .iter()
+            .try_for_each(|s| self.compile_season(s, report))
+    }
+
+    pub fn compile_seasons(&self, seasons: &[SeasonMetadata]) -> Result<CompileReport> {
+        let mut report = CompileReport::default();
+
+        for s in seasons {
+            self.compile_season(s, &mut report)?;
+        }
+
+        Ok(report)
+    }
+
+    /// Compile and package a season. Every episode is recorded in the report. If `keep_going` is
+    /// set, failed episodes don't stop the others, otherwise the first error is returned.
+    pub fn compile_season(
+        &self,
+        season_metadata: &SeasonMetadata,
+        report: &mut CompileReport,
+    ) -> Result<()> {
+        self.events.on_season_started(season_metadata);
+        report
+            .invalid_episodes
+            .extend(season_metadata.invalid_episodes.iter().cloned());
+
+        // Episodes are sorted, so normal episodes are compiled first, then special episodes, then
+        // parts of uploader videos.
+        let episodes: Vec<&EpisodeMetadata> = season_metadata.episodes.iter().collect();
+
+        // Without `keep_going`, episodes that haven't started yet are left out after an error.
+        let has_failed = AtomicBool::new(false);
+        let compile = |episode: &&EpisodeMetadata| {
+            if has_failed.load(Ordering::Relaxed) {
+                return None;
+            }
+
+            let result = self.compile_and_save_episode(episode);
+            if result.is_err() && !self.config.keep_going {
+                has_failed.store(true, Ordering::Relaxed);
+            }
+
+            Some(result)
+        };
+
+        let results: Vec<_> = match self.config.is_parallel {
+            true => episodes.par_iter().map(compile).collect(),
+            false => episodes.iter().map(compile).collect(),
+        };
+
+        let mut error = None;
+
+        for (episode, result) in episodes.into_iter().zip(results) {
+            match result {
+                Some(Ok(s)) => report.push(episode, s),
+                Some(Err(e)) => {
+                    report.push(episode, EpisodeStatus::Failed(e.to_string()));
+
+                    if !self.config.keep_going && error.is_none() {
+                        error = Some(e);
+                    }
+                }
+                None => {}
+            }
+        }
+
+        match error {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+
+    /// Compile an episode without packaging it. `on_episode_finished` isn't sent, since the
+    /// episode only finishes once it's packaged.
+    pub fn compile_episode(&self, episode: &EpisodeMetadata) -> Result<EpisodeStatus> {
+        self.events.on_episode_started(episode);
+
+        self.combine_episode(episode).inspect_err(|e| {
+            self.events.on_error(Some(episode), e);
+        })
+    }
+
+    /// Compile and package an episode, then send `on_episode_finished`.
+    fn compile_and_save_episode(&self, episode: &EpisodeMetadata) -> Result<EpisodeStatus> {
+        // Only compiled episodes are packaged.
+        let result = self.compile_episode(episode).and_then(|s| match s {
+            EpisodeStatus::Succeeded => self
+                .packager
+                .save_episode_with_events(episode, self.events.as_ref())
+                .map(|_| s),
+            s => Ok(s),
+        });
+
+        match &result {
+            Ok(s) => self.events.on_episode_finished(episode, s),
+            Err(e) => self
+                .events
+                .on_episode_finished(episode, &EpisodeStatus::Failed(e.to_string())),
+        }
+
+        result
+    }
+
+    /// Convert the subtitles of the episode and combine them with the audio and video.
+    fn combine_episode(&self, episode: &EpisodeMetadata) -> Result<EpisodeStatus> {
+        if episode.download_status.is_incomplete() && !self.config.include_incomplete {
+            let reason = match episode.download_status.get_percentage() {
+                Some(p) => format!("Download is incomplete ({p:.0}%)"),
+                None => "Download is incomplete".into(),
+            };
+
+            return Ok(EpisodeStatus::Skipped(reason));
+        }
+
+        let subtitle_languages: Vec<String> = match self.config.all_languages {
+            true => episode.get_subtitle_languages()?,
+            false => episode
+                .get_subtitle_languages()?
+                .into_iter()
+                .filter(|l| *l == self.config.language)
+                .collect(),
+        };
+
+        if subtitle_languages.is_empty() {
+            let reason = match self.config.all_languages {
+                true => format!("No subtitles found in {}", episode.path.display()),
+                false => format!(
+                    "No \"{}\" subtitle found in {}",
+                    self.config.language,
+                    episode.path.display()
+                ),
+            };
+
+            return match self.config.missing_subtitle_policy {
+                MissingSubtitlePolicy::Require => Err(Error::SubtitleError(reason)),
+                MissingSubtitlePolicy::Skip => Ok(EpisodeStatus::Skipped(reason)),
+                MissingSubtitlePolicy::MuxWithout => {
+                    self.mux_episode(episode, &[], 0)?;
+
+                    Ok(EpisodeStatus::Succeeded)
+                }
+            };
+        }
+
+        let subtitle_tracks = subtitle_languages
+            .iter()
+            .map(|l| {
+                let track = SubtitleTrack {
+                    path: convert_subtitle(episode, l, &self.config.subtitle_style)
+                        .map_err(|e| Error::SubtitleError(e.to_string()))?
+                        .into(),
+                    language: l.clone(),
+                    title: None,
+                    keep_fonts: false,
+                };
+                self.events.on_subtitle_converted(episode, &track);
+
+                Ok(track)
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let default_track = subtitle_languages
+            .iter()
+            .position(|l| *l == self.config.language)
+            .unwrap_or_default();
+
+        self.mux_episode(episode, &subtitle_tracks, default_track)?;
+
+        Ok(EpisodeStatus::Succeeded)
+    }
+
+    fn mux_episode(
+        &self,
+        episode: &EpisodeMetadata,
+        subtitle_tracks: &[SubtitleTrack],
+        default_track: usize,
+    ) -> Result<()> {
+        let mut tracks = subtitle_tracks.to_vec();
+
+        if let Some(track) = self.convert_danmaku(episode)? {
+            self.events.on_subtitle_converted(episode, &track);
+            tracks.push(track);
+        }
+
+        // Without subtitles, no track is marked as default so the danmaku isn't shown unasked.
+        let default_track = match subtitle_tracks.is_empty() {
+            true => tracks.len(),
+            false => default_track,
+        };
+
+        self.events.on_mux_started(episode);
+        episode.combine_with_progress(
+            self.muxer.as_ref(),
+            &tracks,
+            default_track,
+            self.config.subtitle_type,
+            &self.config.fonts.get_font_files()?,
+            &|p| self.events.on_mux_progress(episode, p),
+        )?;
+        self.events.on_mux_finished(episode);
+
+        Ok(())
+    }
+
+    /// Convert the danmaku of the episode to an SSA track if `danmaku` is set. Danmaku are only
+    /// muxed with soft subtitles.
+    fn convert_danmaku(&self, episode: &EpisodeMetadata) -> Result<Option<SubtitleTrack>> {
+        let (Some(config), SubtitleType::Soft) = (&self.config.danmaku, self.config.subtitle_type)
+        else {
+            return Ok(None);
+        };
+
+        if episode.get_danmaku_path().is_none() {
+            return Ok(None);
+        }
+
+        let binding = episode.path.join("danmaku.ass");
+        let output_path = binding.to_str().ok_or("Path is not valid Unicode")?;
+
+        let ssa = DanmakuFile::new_from_episode(episode)
+            .map_err(|e| Error::SubtitleError(e.to_string()))?
+            .to_ssa(config)?;
+        write(output_path, ssa.to_string())?;
+
+        Ok(Some(SubtitleTrack {
+            path: binding,
+            language: "und".into(),
+            title: Some("Danmaku".into()),
+            // Danmaku use the font of their own config.
+            keep_fonts: true,
+        }))
+    }
+}
+
+/// Convert a subtitle of the episode to SSA. JSON subtitles are styled for the resolution of the
+/// video. Returns the path of the converted subtitle.
+fn convert_subtitle(
+    episode: &EpisodeMetadata,
+    language: &str,
+    style: &SsaStyleConfig,
+) -> Result<String> {
+    let subtitle_path = episode.get_subtitle_path(language)?;
+    let binding = episode.path.join(format!("subtitle.{language}.ass"));
+    let output_subtitle_path = binding.to_str().ok_or("Path is not valid Unicode")?;
+
+    match SubtitleFormat::get_episode_subtitle_type(episode, language)? {
+        SubtitleFormat::Json => {
+            let style = match episode.get_video_resolution() {
+                Some((w, h)) => style.scale_to_resolution(w, h),
+                None => style.clone(),
+            };
+
+            let ssa = JsonSubtitle::new_from_path(subtitle_path)?.to_ssa_with_style(&style);
+            write(output_subtitle_path, ssa.to_string())
+        }
+        SubtitleFormat::Ssa => rename(subtitle_path, output_subtitle_path),
+        SubtitleFormat::Srt => {
+            let ssa = SRTFile::from_str(&read_to_string(subtitle_path)?)?.to_ass();
+            write(output_subtitle_path, ssa.to_string())
+        }
+        SubtitleFormat::Vtt => {
+            let ssa = VTTFile::from_str(&read_to_string(subtitle_path)?)?.to_ass();
+            write(output_subtitle_path, ssa.to_string())
+        }
+    }?;
+
+    Ok(output_subtitle_path.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        metadata::{DownloadStatus, EpisodeId},
+        mp4::tests::get_temporary_path,
+        muxer::MuxJob,
+    };
+    use std::{
+        fs,
+        path::Path,
+        sync::{Arc, Mutex},
+    };
+
+    /// Writes an empty output, except for episodes whose path contains `broken`.
+    struct TestMuxer;
+
+    impl Muxer for TestMuxer {
+        fn mux(&self, job: &MuxJob) -> Result<()> {
+            match job.video_path.to_string_lossy().contains("broken") {
+                true => Err("Cannot mux a broken episode.".into()),
+                false => Ok(fs::write(&job.output_path, "")?),
+            }
+        }
+    }
+
+    /// Create an episode with an empty `video.m4s`.
+    fn test_episode(season_path: &Path, name: &str, number: usize) -> EpisodeMetadata {
+        let path = season_path.join(name);
+        fs::create_dir_all(path.join("64")).unwrap();
+        fs::write(path.join("64").join("video.m4s"), "").unwrap();
+
+        EpisodeMetadata {
+            title: "Anime".into(),
+            episode: EpisodeId::Normal(number),
+            episode_title: None,
+            path,
+            type_tag: "64".into(),
+            duration: None,
+            resolution: None,
+            download_status: DownloadStatus::Completed,
+        }
+    }
+
+    #[test]
+    fn keep_going_compiles_the_other_episodes() {
+        let path = get_temporary_path("keep_going");
+        let season = SeasonMetadata {
+            title: "Anime".into(),
+            path: path.join("season"),
+            episodes: vec![
+                test_episode(&path.join("season"), "ep1", 1),
+                test_episode(&path.join("season"), "broken", 2),
+                test_episode(&path.join("season"), "ep3", 3),
+            ],
+            invalid_episodes: vec![],
+        };
+        let packager = Packager::new(path.join("output")).unwrap();
+        let compiler = Compiler::new(packager.clone())
+            .set_config(CompilerConfig {
+                missing_subtitle_policy: MissingSubtitlePolicy::MuxWithout,
+                keep_going: true,
+                ..Default::default()
+            })
+            .set_muxer(TestMuxer);
+
+        let mut report = CompileReport::default();
+        let result = compiler.compile_season(&season, &mut report);
+        let packaged: Vec<_> = season
+            .episodes
+            .iter()
+            .map(|e| packager.get_episode_package_path(e).is_file())
+            .collect();
+        fs::remove_dir_all(&path).unwrap();
+
+        assert!(result.is_ok());
+        assert_eq!(packaged, [true, false, true]);
+
+        let statuses: Vec<_> = report.episodes.iter().map(|e| &e.status).collect();
+        assert_eq!(
+            statuses,
+            [
+                &EpisodeStatus::Succeeded,
+                &EpisodeStatus::Failed(
+                    "Bilibili Extractor error: Cannot mux a broken episode.".into()
+                ),
+                &EpisodeStatus::Succeeded,
+            ]
+        );
+        assert!(report.has_failures());
+    }
+
+    #[test]
+    fn errors_stop_compiling_without_keep_going() {
+        let path = get_temporary_path("stop_at_error");
+        let season = SeasonMetadata {
+            title: "Anime".into(),
+            path: path.join("season"),
+            episodes: vec![
+                test_episode(&path.join("season"), "ep1", 1),
+                test_episode(&path.join("season"), "broken", 2),
+                test_episode(&path.join("season"), "ep3", 3),
+            ],
+            invalid_episodes: vec![],
+        };
+        let compiler = Compiler::new(Packager::new(path.join("output")).unwrap())
+            .set_config(CompilerConfig {
+                missing_subtitle_policy: MissingSubtitlePolicy::MuxWithout,
+                ..Default::default()
+            })
+            .set_muxer(TestMuxer);
+
+        let mut report = CompileReport::default();
+        let result = compiler.compile_season(&season, &mut report);
+        fs::remove_dir_all(&path).unwrap();
+
+        assert!(result.is_err());
+
+        // The failed episode is recorded, and the episode after it isn't compiled.
+        let statuses: Vec<_> = report
+            .episodes
+            .iter()
+            .map(|e| {
+                (
+                    &e.episode.episode,
+                    matches!(e.status, EpisodeStatus::Failed(_)),
+                )
+            })
+            .collect();
+        assert_eq!(
+            statuses,
+            [
+                (&EpisodeId::Normal(1), false),
+                (&EpisodeId::Normal(2), true)
+            ]
+        );
+    }
+
+    /// Records the episode events it receives.
+    #[derive(Clone, Default)]
+    struct EventRecorder {
+        events: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl EventHandler for EventRecorder {
+        fn on_episode_finished(&self, _episode: &EpisodeMetadata, status: &EpisodeStatus) {
+            let event = match status {
+                EpisodeStatus::Succeeded => "succeeded",
+                EpisodeStatus::Skipped(_) => "skipped",
+                EpisodeStatus::Failed(_) => "failed",
+            };
+            self.events.lock().unwrap().push(event.into());
+        }
+
+        fn on_file_packaged(&self, _episode: &EpisodeMetadata, _path: &Path) {
+            self.events.lock().unwrap().push("packaged".into());
+        }
+
+        fn on_error(&self, _episode: Option<&EpisodeMetadata>, _error: &Error) {
+            self.events.lock().unwrap().push("error".into());
+        }
+    }
+
+    #[test]
+    fn episodes_finish_after_they_are_packaged() {
+        let path = get_temporary_path("finish_after_packaging");
+        let episode = test_episode(&path.join("season"), "ep1", 1);
+        let packager = Packager::new(path.join("output")).unwrap();
+        let recorder = EventRecorder::default();
+        let compiler = Compiler::new(packager.clone())
+            .set_config(CompilerConfig {
+                missing_subtitle_policy: MissingSubtitlePolicy::MuxWithout,
+                keep_going: true,
+                ..Default::default()
+            })
+            .set_muxer(TestMuxer)
+            .set_event_handler(recorder.clone());
+        let season = SeasonMetadata {
+            title: "Anime".into(),
+            path: path.join("season"),
+            episodes: vec![episode],
+            invalid_episodes: vec![],
+        };
+        let season_package_path = packager.get_season_package_path(&season.episodes[0]);
+
+        let mut report = CompileReport::default();
+        compiler.compile_season(&season, &mut report).unwrap();
+        let packaged_events = recorder.events.lock().unwrap().split_off(0);
+
+        // A file where the season directory should be can't be packaged into.
+        fs::remove_dir_all(&season_package_path).unwrap();
+        fs::write(&season_package_path, "").unwrap();
+        compiler.compile_season(&season, &mut report).unwrap();
+        let failed_events = recorder.events.lock().unwrap().split_off(0);
+        fs::remove_dir_all(&path).unwrap();
+
+        assert_eq!(packaged_events, ["packaged", "succeeded"]);
+        assert_eq!(failed_events, ["error", "failed"]);
+        assert!(matches!(
+            &report.episodes[1].status,
+            EpisodeStatus::Failed(r) if r.starts_with("Packaging error")
+        ));
+    }
+}
@@ -0,0 +1,793 @@
+use crate::{
+    error::{Error, Result},
+    metadata::EpisodeMetadata,
+    subtitle::new_time,
+};
+use quick_xml::{events::Event, Reader};
+use regex::Regex;
+use rsubs_lib::{
+    ssa::{SSAEvent, SSAFile, SSAStyle},
+    util::color::{Alignment, Color, ColorType, TRANSPARENT, WHITET},
+};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, fs, path::Path, str::FromStr};
+
+/// Seconds a top or bottom danmaku stays on the screen.
+const FIXED_DURATION: f32 = 4.;
+/// Font size of a normal danmaku in `danmaku.xml`. Small and large ones are 18 and 36.
+const NORMAL_FONT_SIZE: f32 = 25.;
+
+/// Contains every danmaku (bullet comment) inside a Bilibili `danmaku.xml`.
+///
+/// # Convert to SSA
+///
+/// ```
+/// use bilibili_extractor_lib::danmaku::{DanmakuConfig, DanmakuFile};
+///
+/// let danmaku_file: DanmakuFile = r#"<?xml version="1.0" encoding="UTF-8"?>
+/// <i>
+///     <d p="1.5,1,25,16777215,1700000000,0,abcdef,1">Hello</d>
+///     <d p="2,5,25,16711680,1700000000,0,abcdef,2">Top &amp; red</d>
+/// </i>"#
+///     .parse()
+///     .unwrap();
+///
+/// assert_eq!(danmaku_file.danmaku.len(), 2);
+///
+/// println!("{}", danmaku_file.to_ssa(&DanmakuConfig::default()).unwrap());
+/// ```
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, PartialOrd)]
+pub struct DanmakuFile {
+    pub danmaku: Vec<Danmaku>,
+}
+
+/// A single danmaku.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, PartialOrd)]
+pub struct Danmaku {
+    /// Seconds into the video the danmaku appears at.
+    pub time: f32,
+    pub mode: DanmakuMode,
+    /// Font size used by Bilibili, where `25` is a normal danmaku.
+    pub font_size: u32,
+    /// Color as `0xRRGGBB`.
+    pub color: u32,
+    pub content: String,
+}
+
+/// How a danmaku moves. Reverse, positioned, and scripted danmaku are not supported.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum DanmakuMode {
+    /// Moves from right to left.
+    Scroll,
+    /// Stays at the top center.
+    Top,
+    /// Stays at the bottom center.
+    Bottom,
+}
+
+/// Settings of the SSA track made from danmaku.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct DanmakuConfig {
+    pub font_name: String,
+    /// Font size of a normal danmaku. Small and large danmaku are scaled from it.
+    pub font_size: f32,
+    pub play_res_x: u32,
+    pub play_res_y: u32,
+    /// Seconds a scrolling danmaku takes to cross the screen.
+    pub scroll_duration: f32,
+    /// Opacity of every danmaku, from `0` to `1`.
+    pub opacity: f32,
+    /// Most danmaku shown at the same time. Danmaku over the limit are dropped.
+    pub max_on_screen: Option<usize>,
+    /// Modes of the danmaku that are shown.
+    pub modes: Vec<DanmakuMode>,
+    /// Danmaku smaller than this Bilibili font size are dropped, e.g. `25` drops small danmaku.
+    pub min_font_size: u32,
+    /// Danmaku containing any of these keywords are dropped. Case is ignored.
+    pub blocked_keywords: Vec<String>,
+    /// Danmaku matching any of these regular expressions are dropped.
+    pub blocked_patterns: Vec<String>,
+    /// Danmaku with the same content as one shown less than this many seconds before are dropped.
+    pub dedup_window: Option<f32>,
+}
+
+/// A danmaku already placed on the screen.
+#[derive(Debug, Clone, Copy)]
+struct PlacedDanmaku {
+    start: f32,
+    end: f32,
+    y: f32,
+    width: f32,
+    height: f32,
+    /// Pixels per second, `0` for danmaku that don't move.
+    speed: f32,
+}
+
+impl Default for DanmakuConfig {
+    fn default() -> Self {
+        Self {
+            font_name: "Microsoft YaHei".into(),
+            font_size: 48.,
+            play_res_x: 1920,
+            play_res_y: 1080,
+            scroll_duration: 8.,
+            opacity: 1.,
+            max_on_screen: None,
+            modes: vec![DanmakuMode::Scroll, DanmakuMode::Top, DanmakuMode::Bottom],
+            min_font_size: 0,
+            blocked_keywords: vec![],
+            blocked_patterns: vec![],
+            dedup_window: None,
+        }
+    }
+}
+
+impl DanmakuMode {
+    /// Get the mode from the mode number used by Bilibili. Returns `None` for unsupported modes.
+    pub fn new_from_code(code: u8) -> Option<Self> {
+        match code {
+            1..=3 => Some(Self::Scroll),
+            4 => Some(Self::Bottom),
+            5 => Some(Self::Top),
+            _ => None,
+        }
+    }
+}
+
+impl Danmaku {
+    /// Parse the `p` attribute of a `<d>` element, e.g. `12.5,1,25,16777215,...`. Returns `None`
+    /// if it's invalid or uses an unsupported mode.
+    fn new_from_attribute(attribute: &str, content: String) -> Option<Self> {
+        let mut fields = attribute.split(',');
+
+        Some(Self {
+            time: fields.next()?.parse().ok()?,
+            mode: DanmakuMode::new_from_code(fields.next()?.parse().ok()?)?,
+            font_size: fields.next()?.parse().ok()?,
+            color: fields.next()?.parse().ok()?,
+            content,
+        })
+    }
+}
+
+impl DanmakuFile {
+    /// Fetch the danmaku of an episode.
+    pub fn new_from_episode(episode: &EpisodeMetadata) -> Result<Self> {
+        Self::new_from_path(
+            episode
+                .get_danmaku_path()
+                .ok_or(format!("No danmaku found in {}.", episode.path.display()))?,
+        )
+    }
+
+    /// Create a `DanmakuFile` from path.
+    pub fn new_from_path(path: impl AsRef<Path>) -> Result<Self> {
+        fs::read_to_string(path)?.parse()
+    }
+
+    /// Get the danmaku that pass the mode, font size, keyword, pattern, and duplicate filters of
+    /// `config`, sorted by time.
+    ///
+    /// Returns `Error::InputError` if a blocked pattern isn't a valid regular expression.
+    pub fn filter(&self, config: &DanmakuConfig) -> Result<Self> {
+        let blocked_keywords: Vec<String> = config
+            .blocked_keywords
+            .iter()
+            .map(|k| k.to_lowercase())
+            .collect();
+        let blocked_patterns = config
+            .blocked_patterns
+            .iter()
+            .map(|p| {
+                Regex::new(p)
+                    .map_err(|e| Error::InputError(format!("Invalid danmaku pattern \"{p}\": {e}")))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut danmaku: Vec<&Danmaku> = self.danmaku.iter().collect();
+        danmaku.sort_by(|a, b| a.time.total_cmp(&b.time));
+
+        // Time each content was last shown at.
+        let mut shown_at: HashMap<&str, f32> = HashMap::new();
+
+        let danmaku = danmaku
+            .into_iter()
+            .filter(|d| {
+                let content = d.content.to_lowercase();
+
+                config.modes.contains(&d.mode)
+                    && d.font_size >= config.min_font_size
+                    && !blocked_keywords.iter().any(|k| content.contains(k))
+                    && !blocked_patterns.iter().any(|p| p.is_match(&d.content))
+            })
+            .filter(|d| {
+                let Some(window) = config.dedup_window else {
+                    return true;
+                };
+
+                match shown_at.get(d.content.trim()) {
+                    Some(t) if d.time - t < window => false,
+                    _ => {
+                        shown_at.insert(d.content.trim(), d.time);
+                        true
+                    }
+                }
+            })
+            .cloned()
+            .collect();
+
+        Ok(Self { danmaku })
+    }
+
+    /// Convert the danmaku that pass the filters of `config` to `SSAFile`. Danmaku are laid out so
+    /// they don't overlap, and danmaku that don't fit on the screen are dropped.
+    ///
+    /// Returns `Error::InputError` if a blocked pattern isn't a valid regular expression.
+    pub fn to_ssa(&self, config: &DanmakuConfig) -> Result<SSAFile> {
+        let screen_width = config.play_res_x as f32;
+        let screen_height = config.play_res_y as f32;
+        let alpha = ((1. - config.opacity.clamp(0., 1.)) * 255.).round() as u8;
+
+        let mut placed: HashMap<DanmakuMode, Vec<PlacedDanmaku>> = HashMap::new();
+        // End times of the danmaku shown so far, used to limit the danmaku on the screen.
+        let mut shown_until: Vec<f32> = vec![];
+        let mut ass_events = vec![];
+
+        for d in self.filter(config)?.danmaku {
+            if let Some(max_on_screen) = config.max_on_screen {
+                shown_until.retain(|&t| t > d.time);
+
+                if shown_until.len() >= max_on_screen {
+                    continue;
+                }
+            }
+
+            let font_size = config.font_size * d.font_size as f32 / NORMAL_FONT_SIZE;
+            let lines: Vec<&str> = d.content.lines().collect();
+            let width = lines
+                .iter()
+                .map(|l| estimate_text_width(l, font_size))
+                .fold(0., f32::max);
+            let height = font_size * lines.len().max(1) as f32;
+
+            let (duration, speed) = match d.mode {
+                DanmakuMode::Scroll => (
+                    config.scroll_duration,
+                    (screen_width + width) / config.scroll_duration,
+                ),
+                DanmakuMode::Top | DanmakuMode::Bottom => (FIXED_DURATION, 0.),
+            };
+            let new_danmaku = PlacedDanmaku {
+                start: d.time,
+                end: d.time + duration,
+                y: 0.,
+                width,
+                height,
+                speed,
+            };
+
+            let Some(y) = place_danmaku(
+                placed.entry(d.mode).or_default(),
+                new_danmaku,
+                screen_width,
+                screen_height,
+            ) else {
+                continue;
+            };
+            shown_until.push(d.time + duration);
+
+            let position = match d.mode {
+                DanmakuMode::Scroll => {
+                    format!("\\an7\\move({screen_width:.0},{y:.0},{:.0},{y:.0})", -width)
+                }
+                DanmakuMode::Top => format!("\\an8\\pos({:.0},{y:.0})", screen_width / 2.),
+                DanmakuMode::Bottom => format!(
+                    "\\an2\\pos({:.0},{:.0})",
+                    screen_width / 2.,
+                    screen_height - y
+                ),
+            };
+
+            ass_events.push(SSAEvent {
+                style: "Danmaku".into(),
+                line_start: new_time(d.time),
+                line_end: new_time(d.time + duration),
+                line_text: format!(
+                    "{{{position}{}{}}}{}",
+                    match font_size == config.font_size {
+                        true => String::new(),
+                        false => format!("\\fs{font_size:.0}"),
+                    },
+                    get_color_tags(d.color),
+                    escape_ssa_text(&d.content)
+                ),
+                ..Default::default()
+            });
+        }
+
+        let mut ass_info = HashMap::new();
+        ass_info.insert("Title".into(), "Bilibili Danmaku".into());
+        ass_info.insert("ScriptType".into(), "v4.00+".into());
+        // Danmaku are never wrapped, otherwise scrolling ones would be broken into lines.
+        ass_info.insert("WrapStyle".into(), "2".into());
+        ass_info.insert("ScaledBorderAndShadow".into(), "yes".into());
+        ass_info.insert("YCbCr Matrix".into(), "TV.601".into());
+        ass_info.insert("PlayResX".into(), config.play_res_x.to_string());
+        ass_info.insert("PlayResY".into(), config.play_res_y.to_string());
+
+        let ass_style = SSAStyle {
+            name: "Danmaku".into(),
+            fontname: config.font_name.clone(),
+            fontsize: config.font_size,
+            firstcolor: ColorType::SSAColor(Color { a: alpha, ..WHITET }),
+            // `rsubs_lib`'s `TRANSPARENT` has no alpha, which is opaque black in SSA.
+            outlinecolor: ColorType::SSAColor(Color {
+                a: alpha,
+                ..TRANSPARENT
+            }),
+            // `rsubs_lib` writes `false` as `-1`, which is `true` in SSA.
+            bold: false,
+            scalex: 100.,
+            scaley: 100.,
+            outline: 2.,
+            shadow: 0.,
+            alignment: Alignment::TopLeft,
+            vmargin: 0,
+            ..Default::default()
+        };
+
+        Ok(SSAFile {
+            events: ass_events,
+            styles: vec![ass_style],
+            info: ass_info,
+            format: ".ass".into(),
+        })
+    }
+}
+
+impl FromStr for DanmakuFile {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let to_error = |e: quick_xml::Error| Error::SubtitleError(format!("Invalid danmaku: {e}"));
+
+        let mut reader = Reader::from_str(s);
+        let mut danmaku = vec![];
+        let mut attribute = None;
+        let mut content = String::new();
+
+        loop {
+            match reader.read_event().map_err(to_error)? {
+                Event::Start(e) if e.name().as_ref() == b"d" => {
+                    attribute = e
+                        .try_get_attribute("p")
+                        .map_err(|e| to_error(e.into()))?
+                        .map(|a| a.unescape_value().map(|v| v.into_owned()))
+                        .transpose()
+                        .map_err(to_error)?;
+                    content.clear();
+                }
+                Event::Text(e) if attribute.is_some() => {
+                    content.push_str(&e.unescape().map_err(to_error)?)
+                }
+                Event::CData(e) if attribute.is_some() => {
+                    content.push_str(&String::from_utf8_lossy(&e))
+                }
+                Event::End(e) if e.name().as_ref() == b"d" => {
+                    if let Some(d) = attribute
+                        .take()
+                        .and_then(|a| Danmaku::new_from_attribute(&a, content.clone()))
+                    {
+                        danmaku.push(d);
+                    }
+                }
+                Event::Eof => break,
+                _ => {}
+            }
+        }
+
+        Ok(Self { danmaku })
+    }
+}
+
+/// Find the highest position where a danmaku doesn't overlap the ones already on the screen, and
+/// place it there. Positions are measured from the top for top and scrolling danmaku, and from
+/// the bottom for bottom danmaku.
+fn place_danmaku(
+    placed: &mut Vec<PlacedDanmaku>,
+    mut danmaku: PlacedDanmaku,
+    screen_width: f32,
+    screen_height: f32,
+) -> Option<f32> {
+    placed.retain(|p| p.end > danmaku.start);
+
+    let is_blocking = |p: &PlacedDanmaku, y: f32| {
+        let is_overlapping = y < p.y + p.height && p.y < y + danmaku.height;
+
+        match danmaku.speed > 0. {
+            // The tail of the earlier danmaku must be on the screen, and the new one must not
+            // catch up with it before it leaves.
+            true => {
+                is_overlapping
+                    && (danmaku.start < p.start + p.width / p.speed
+                        || danmaku.start + screen_width / danmaku.speed < p.end)
+            }
+            false => is_overlapping,
+        }
+    };
+
+    let mut positions: Vec<f32> = std::iter::once(0.)
+        .chain(placed.iter().map(|p| p.y + p.height))
+        .collect();
+    positions.sort_by(f32::total_cmp);
+
+    let y = positions.into_iter().find(|&y| {
+        y + danmaku.height <= screen_height && !placed.iter().any(|p| is_blocking(p, y))
+    })?;
+
+    danmaku.y = y;
+    placed.push(danmaku);
+
+    Some(y)
+}
+
+/// Estimate the width of a line. Fonts aren't measured, so ASCII characters are assumed to be
+/// half as wide as others.
+fn estimate_text_width(text: &str, font_size: f32) -> f32 {
+    text.chars()
+        .map(|c| match c.is_ascii() {
+            true => font_size / 2.,
+            false => font_size,
+        })
+        .sum()
+}
+
+/// Get the override tags that color a danmaku. White danmaku use the style's color, and dark ones
+/// get a white outline so they stay readable.
+fn get_color_tags(color: u32) -> String {
+    let [_, red, green, blue] = color.to_be_bytes();
+    let mut tags = String::new();
+
+    if color & 0xFFFFFF != 0xFFFFFF {
+        tags.push_str(&format!("\\c&H{blue:02X}{green:02X}{red:02X}&"));
+    }
+    if (red as u32 * 299 + green as u32 * 587 + blue as u32 * 114) / 1000 < 48 {
+        tags.push_str("\\3c&HFFFFFF&");
+    }
+
+    tags
+}
+
+/// Escape text so characters used by SSA override tags are shown as they are. libass has no
+/// escape for `\`, so a word joiner is put after it to break sequences like `\N`.
+fn escape_ssa_text(text: &str) -> String {
+    text.replace('\\', "\\\u{2060}")
+        .replace('{', "\\{")
+        .replace('}', "\\}")
+        .replace("\r\n", "\\N")
+        .replace('\n', "\\N")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(danmaku: &str) -> DanmakuFile {
+        format!("<?xml version=\"1.0\" encoding=\"UTF-8\"?><i>{danmaku}</i>")
+            .parse()
+            .unwrap()
+    }
+
+    fn danmaku(time: f32, mode: DanmakuMode, content: &str) -> Danmaku {
+        Danmaku {
+            time,
+            mode,
+            font_size: 25,
+            color: 0xFFFFFF,
+            content: content.into(),
+        }
+    }
+
+    /// Get the position of each SSA event, e.g. `\\pos(960,0)`.
+    fn get_positions(ssa: &SSAFile) -> Vec<(f32, f32)> {
+        let regex = Regex::new(r"\\(?:move|pos)\((-?\d+),(-?\d+)").unwrap();
+
+        ssa.events
+            .iter()
+            .map(|e| {
+                let captures = regex.captures(&e.line_text).unwrap();
+                (captures[1].parse().unwrap(), captures[2].parse().unwrap())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn danmaku_are_parsed() {
+        let danmaku_file = parse(concat!(
+            r#"<d p="1.5,1,25,16777215,1700000000,0,abcdef,1">Hello</d>"#,
+            r#"<d p="2,4,18,255,1700000000,0,abcdef,2">a &amp; b</d>"#,
+            r#"<d p="3,5,36,0"><![CDATA[<raw>]]></d>"#,
+        ));
+
+        assert_eq!(
+            danmaku_file.danmaku,
+            vec![
+                danmaku(1.5, DanmakuMode::Scroll, "Hello"),
+                Danmaku {
+                    font_size: 18,
+                    color: 255,
+                    ..danmaku(2., DanmakuMode::Bottom, "a & b")
+                },
+                Danmaku {
+                    font_size: 36,
+                    color: 0,
+                    ..danmaku(3., DanmakuMode::Top, "<raw>")
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn invalid_danmaku_are_skipped() {
+        let danmaku_file = parse(concat!(
+            r#"<d p="1,1,25">Short</d>"#,
+            r#"<d p="">Empty</d>"#,
+            r#"<d p="one,1,25,16777215">Not a number</d>"#,
+            r#"<d p="1,1,-25,16777215">Negative size</d>"#,
+            r#"<d p="1,7,25,16777215">Scripted</d>"#,
+            r#"<d>No attribute</d>"#,
+            r#"<d p="1,1,25,16777215">Valid</d>"#,
+        ));
+
+        assert_eq!(
+            danmaku_file.danmaku,
+            vec![danmaku(1., DanmakuMode::Scroll, "Valid")]
+        );
+    }
+
+    #[test]
+    fn danmaku_are_placed_in_separate_rows() {
+        let danmaku_file = DanmakuFile {
+            danmaku: [DanmakuMode::Scroll, DanmakuMode::Top, DanmakuMode::Bottom]
+                .into_iter()
+                .flat_map(|mode| (0..3).map(move |_| danmaku(1., mode, "Hello")))
+                .collect(),
+        };
+        let config = DanmakuConfig::default();
+
+        let ssa = danmaku_file.to_ssa(&config).unwrap();
+        let positions = get_positions(&ssa);
+
+        // Rows are one font size apart, so danmaku of the same mode don't overlap.
+        let rows: Vec<f32> = positions.iter().map(|(_, y)| *y).collect();
+        assert_eq!(rows, vec![0., 48., 96., 0., 48., 96., 1080., 1032., 984.]);
+        assert!(ssa.events[..3]
+            .iter()
+            .all(|e| e.line_text.starts_with("{\\an7\\move(1920,")));
+        assert!(ssa.events[3..6]
+            .iter()
+            .all(|e| e.line_text.starts_with("{\\an8\\pos(960,")));
+        assert!(ssa.events[6..]
+            .iter()
+            .all(|e| e.line_text.starts_with("{\\an2\\pos(960,")));
+    }
+
+    #[test]
+    fn rows_are_reused_after_danmaku_leave() {
+        let danmaku_file = DanmakuFile {
+            danmaku: vec![
+                danmaku(0., DanmakuMode::Top, "First"),
+                danmaku(1., DanmakuMode::Top, "Second"),
+                danmaku(FIXED_DURATION, DanmakuMode::Top, "Third"),
+            ],
+        };
+
+        let ssa = danmaku_file.to_ssa(&DanmakuConfig::default()).unwrap();
+
+        assert_eq!(
+            get_positions(&ssa),
+            vec![(960., 0.), (960., 48.), (960., 0.)]
+        );
+    }
+
+    #[test]
+    fn danmaku_that_do_not_fit_are_dropped() {
+        let danmaku_file = DanmakuFile {
+            danmaku: (0..4)
+                .map(|_| danmaku(1., DanmakuMode::Top, "Hello"))
+                .collect(),
+        };
+        let config = DanmakuConfig {
+            play_res_y: 150,
+            ..Default::default()
+        };
+
+        let ssa = danmaku_file.to_ssa(&config).unwrap();
+
+        assert_eq!(get_positions(&ssa).len(), 3);
+    }
+
+    #[test]
+    fn override_tags_are_escaped() {
+        assert_eq!(escape_ssa_text("{\\b1}"), "\\{\\\u{2060}b1\\}");
+        assert_eq!(escape_ssa_text("a\\Nb"), "a\\\u{2060}Nb");
+        assert_eq!(escape_ssa_text("a\nb\r\nc"), "a\\Nb\\Nc");
+
+        let danmaku_file = DanmakuFile {
+            danmaku: vec![danmaku(0., DanmakuMode::Scroll, "{\\pos(0,0)}")],
+        };
+        let ssa = danmaku_file.to_ssa(&DanmakuConfig::default()).unwrap();
+
+        assert!(ssa.events[0]
+            .line_text
+            .ends_with("}\\{\\\u{2060}pos(0,0)\\}"));
+    }
+
+    fn get_contents(danmaku_file: &DanmakuFile) -> Vec<&str> {
+        danmaku_file
+            .danmaku
+            .iter()
+            .map(|d| d.content.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn danmaku_are_filtered_by_mode() {
+        let danmaku_file = DanmakuFile {
+            danmaku: vec![
+                danmaku(0., DanmakuMode::Scroll, "Scroll"),
+                danmaku(1., DanmakuMode::Top, "Top"),
+                danmaku(2., DanmakuMode::Bottom, "Bottom"),
+            ],
+        };
+        let config = DanmakuConfig {
+            modes: vec![DanmakuMode::Scroll, DanmakuMode::Bottom],
+            ..Default::default()
+        };
+
+        let filtered = danmaku_file.filter(&config).unwrap();
+
+        assert_eq!(get_contents(&filtered), vec!["Scroll", "Bottom"]);
+    }
+
+    #[test]
+    fn danmaku_are_filtered_by_font_size() {
+        let danmaku_file = DanmakuFile {
+            danmaku: [18, 25, 36]
+                .into_iter()
+                .map(|font_size| Danmaku {
+                    font_size,
+                    ..danmaku(0., DanmakuMode::Scroll, &font_size.to_string())
+                })
+                .collect(),
+        };
+        let config = DanmakuConfig {
+            min_font_size: 25,
+            ..Default::default()
+        };
+
+        let filtered = danmaku_file.filter(&config).unwrap();
+
+        assert_eq!(get_contents(&filtered), vec!["25", "36"]);
+    }
+
+    #[test]
+    fn danmaku_are_filtered_by_keywords_ignoring_case() {
+        let danmaku_file = DanmakuFile {
+            danmaku: vec![
+                danmaku(0., DanmakuMode::Scroll, "SPOILER ahead"),
+                danmaku(1., DanmakuMode::Scroll, "Nice"),
+                danmaku(2., DanmakuMode::Scroll, "a Spoiler"),
+            ],
+        };
+        let config = DanmakuConfig {
+            blocked_keywords: vec!["spoiler".into()],
+            ..Default::default()
+        };
+
+        let filtered = danmaku_file.filter(&config).unwrap();
+
+        assert_eq!(get_contents(&filtered), vec!["Nice"]);
+    }
+
+    #[test]
+    fn danmaku_are_filtered_by_patterns() {
+        let danmaku_file = DanmakuFile {
+            danmaku: vec![
+                danmaku(0., DanmakuMode::Scroll, "2333333"),
+                danmaku(1., DanmakuMode::Scroll, "Nice 233"),
+                danmaku(2., DanmakuMode::Scroll, "hhhh"),
+            ],
+        };
+        let config = DanmakuConfig {
+            blocked_patterns: vec!["^2(33)+3*$".into(), "^h+$".into()],
+            ..Default::default()
+        };
+
+        let filtered = danmaku_file.filter(&config).unwrap();
+
+        assert_eq!(get_contents(&filtered), vec!["Nice 233"]);
+    }
+
+    #[test]
+    fn invalid_patterns_are_input_errors() {
+        let config = DanmakuConfig {
+            blocked_patterns: vec!["(unclosed".into()],
+            ..Default::default()
+        };
+
+        assert!(matches!(
+            DanmakuFile::default().filter(&config),
+            Err(Error::InputError(_))
+        ));
+        assert!(matches!(
+            DanmakuFile::default().to_ssa(&config),
+            Err(Error::InputError(_))
+        ));
+    }
+
+    #[test]
+    fn repeated_danmaku_are_dropped_within_the_window() {
+        let danmaku_file = DanmakuFile {
+            danmaku: vec![
+                danmaku(5., DanmakuMode::Scroll, "Again"),
+                danmaku(0., DanmakuMode::Scroll, "Again"),
+                danmaku(1., DanmakuMode::Scroll, " Again "),
+                danmaku(2., DanmakuMode::Scroll, "Other"),
+                danmaku(12., DanmakuMode::Scroll, "Again"),
+            ],
+        };
+        let config = DanmakuConfig {
+            dedup_window: Some(10.),
+            ..Default::default()
+        };
+
+        let filtered = danmaku_file.filter(&config).unwrap();
+
+        assert_eq!(
+            filtered
+                .danmaku
+                .iter()
+                .map(|d| (d.time, d.content.as_str()))
+                .collect::<Vec<_>>(),
+            vec![(0., "Again"), (2., "Other"), (12., "Again")]
+        );
+        assert_eq!(
+            danmaku_file
+                .filter(&DanmakuConfig::default())
+                .unwrap()
+                .danmaku
+                .len(),
+            5
+        );
+    }
+
+    #[test]
+    fn danmaku_over_the_limit_are_dropped() {
+        let danmaku_file = DanmakuFile {
+            danmaku: vec![
+                danmaku(0., DanmakuMode::Scroll, "First"),
+                danmaku(1., DanmakuMode::Top, "Second"),
+                danmaku(2., DanmakuMode::Bottom, "Dropped"),
+                danmaku(3., DanmakuMode::Scroll, "Dropped"),
+                danmaku(FIXED_DURATION + 1., DanmakuMode::Scroll, "Third"),
+            ],
+        };
+        let config = DanmakuConfig {
+            max_on_screen: Some(2),
+            ..Default::default()
+        };
+
+        let ssa = danmaku_file.to_ssa(&config).unwrap();
+
+        // The top danmaku has left when the third one starts, but the first is still scrolling.
+        assert_eq!(
+            ssa.events
+                .iter()
+                .map(|e| e.line_text.rsplit('}').next().unwrap())
+                .collect::<Vec<_>>(),
+            vec!["First", "Second", "Third"]
+        );
+    }
+}
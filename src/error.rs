@@ -11,6 +11,26 @@ pub enum Error {
     #[error("Parse Integer error: {0}")]
     ParseIntError(#[from] std::num::ParseIntError),
 
+    #[error(
+        "FFmpeg exited with {}: {command}\n{stderr}",
+        .code.map_or("no exit code".into(), |c| format!("exit code {c}"))
+    )]
+    FFmpegError {
+        /// `None` if `ffmpeg` was terminated by a signal.
+        code: Option<i32>,
+        command: String,
+        stderr: String,
+    },
+
+    #[error("Input error: {0}")]
+    InputError(String),
+
+    #[error("Subtitle error: {0}")]
+    SubtitleError(String),
+
+    #[error("Packaging error: {0}")]
+    PackagingError(String),
+
     #[error("Bilibili Extractor error: {0}")]
     BilibiliExtractorError(String),
 }
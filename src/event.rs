@@ -0,0 +1,64 @@
+use crate::{
+    combiner::SubtitleTrack,
+    error::Error,
+    metadata::{EpisodeMetadata, SeasonMetadata},
+    muxer::MuxProgress,
+    report::EpisodeStatus,
+};
+use std::path::Path;
+
+/// Receives events while seasons are compiled and packaged. Every method does nothing by
+/// default, so only the events of interest need to be implemented. `()` ignores every event.
+///
+/// Events of different episodes may arrive from different threads when compiling in parallel.
+///
+/// # Count packaged files
+///
+/// ```
+/// use bilibili_extractor_lib::{event::EventHandler, metadata::EpisodeMetadata};
+/// use std::{
+///     path::Path,
+///     sync::atomic::{AtomicUsize, Ordering},
+/// };
+///
+/// #[derive(Default)]
+/// struct PackagedCounter {
+///     count: AtomicUsize,
+/// }
+///
+/// impl EventHandler for PackagedCounter {
+///     fn on_file_packaged(&self, _episode: &EpisodeMetadata, _path: &Path) {
+///         self.count.fetch_add(1, Ordering::Relaxed);
+///     }
+/// }
+/// ```
+pub trait EventHandler: Send + Sync {
+    /// A season is about to be compiled or packaged.
+    fn on_season_started(&self, _season: &SeasonMetadata) {}
+
+    /// An episode is about to be compiled.
+    fn on_episode_started(&self, _episode: &EpisodeMetadata) {}
+
+    /// A subtitle of an episode was converted to SSA.
+    fn on_subtitle_converted(&self, _episode: &EpisodeMetadata, _track: &SubtitleTrack) {}
+
+    /// The `Muxer` started combining an episode.
+    fn on_mux_started(&self, _episode: &EpisodeMetadata) {}
+
+    /// The `Muxer` reported progress on an episode.
+    fn on_mux_progress(&self, _episode: &EpisodeMetadata, _progress: &MuxProgress) {}
+
+    /// The `Muxer` finished combining an episode.
+    fn on_mux_finished(&self, _episode: &EpisodeMetadata) {}
+
+    /// An episode was compiled and packaged, skipped, or failed to compile or package.
+    fn on_episode_finished(&self, _episode: &EpisodeMetadata, _status: &EpisodeStatus) {}
+
+    /// A compiled episode was moved or copied to `path`.
+    fn on_file_packaged(&self, _episode: &EpisodeMetadata, _path: &Path) {}
+
+    /// Something failed. `episode` is `None` if the error isn't about a single episode.
+    fn on_error(&self, _episode: Option<&EpisodeMetadata>, _error: &Error) {}
+}
+
+impl EventHandler for () {}
@@ -0,0 +1,364 @@
+use crate::error::{Error, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{read, read_dir},
+    path::{Path, PathBuf},
+};
+
+/// Font files attached to soft-subbed episodes, so players can show the subtitles without
+/// having the fonts installed.
+///
+/// ```
+/// use bilibili_extractor_lib::font::FontConfig;
+/// use std::fs;
+///
+/// let font_dir = std::env::temp_dir().join("bilibili-extractor-font-config-doctest");
+/// fs::create_dir_all(&font_dir).unwrap();
+/// ["NotoSans-Bold.otf", "NotoSans-Regular.ttf", "README.txt"]
+///     .iter()
+///     .for_each(|f| fs::write(font_dir.join(f), []).unwrap());
+///
+/// let font_config = FontConfig {
+///     font_dir: Some(font_dir.clone()),
+///     font_files: vec!["NotoSansThai-Regular.ttf".into()],
+/// };
+/// let font_files = font_config.get_font_files().unwrap();
+/// fs::remove_dir_all(&font_dir).unwrap();
+///
+/// // Fonts in `font_dir` come first, sorted by name, and other files are ignored.
+/// assert_eq!(
+///     font_files,
+///     vec![
+///         font_dir.join("NotoSans-Bold.otf"),
+///         font_dir.join("NotoSans-Regular.ttf"),
+///         "NotoSansThai-Regular.ttf".into(),
+///     ]
+/// );
+/// ```
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(default)]
+pub struct FontConfig {
+    /// Directory whose font files are all attached.
+    pub font_dir: Option<PathBuf>,
+    /// Font files attached after the ones in `font_dir`.
+    pub font_files: Vec<PathBuf>,
+}
+
+impl FontConfig {
+    /// Get every font file to attach. Files in `font_dir` are sorted by name.
+    pub fn get_font_files(&self) -> Result<Vec<PathBuf>> {
+        let mut font_files = vec![];
+
+        if let Some(font_dir) = &self.font_dir {
+            let mut dir_font_files = read_dir(font_dir)
+                .map_err(|e| {
+                    Error::InputError(format!("Cannot read fonts in {}: {e}", font_dir.display()))
+                })?
+                .map(|e| Ok(e?.path()))
+                .collect::<Result<Vec<_>>>()?
+                .into_iter()
+                .filter(|p| p.is_file() && is_font_file(p))
+                .collect::<Vec<_>>();
+            dir_font_files.sort();
+
+            font_files.extend(dir_font_files);
+        }
+
+        font_files.extend(self.font_files.iter().cloned());
+
+        Ok(font_files)
+    }
+}
+
+/// Returns `true` if the file has a TrueType or OpenType extension.
+pub fn is_font_file(path: &Path) -> bool {
+    matches!(
+        path.extension()
+            .map(|e| e.to_string_lossy().to_lowercase())
+            .as_deref(),
+        Some("ttf" | "otf" | "ttc" | "otc")
+    )
+}
+
+/// Get the media type of a font file, used for Matroska attachments.
+pub fn get_font_media_type(path: &Path) -> &'static str {
+    match path
+        .extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .as_deref()
+    {
+        Some("otf") => "font/otf",
+        Some("ttc" | "otc") => "font/collection",
+        _ => "font/ttf",
+    }
+}
+
+/// Get the family name of a TrueType or OpenType font, which is the name used by the `Fontname`
+/// of ASS styles. Collections use their first font.
+pub fn get_font_family_name(path: impl AsRef<Path>) -> Result<String> {
+    let path = path.as_ref();
+    let data = read(path)
+        .map_err(|e| Error::InputError(format!("Cannot read font {}: {e}", path.display())))?;
+
+    parse_font_family_name(&data).ok_or_else(|| {
+        Error::InputError(format!("Cannot read the font name of {}", path.display()))
+    })
+}
+
+fn parse_font_family_name(data: &[u8]) -> Option<String> {
+    let font_offset = match data.get(0..4)? {
+        b"ttcf" => read_u32(data, 12)? as usize,
+        _ => 0,
+    };
+
+    let table_count = read_u16(data, font_offset.checked_add(4)?)? as usize;
+    let name_table = (0..table_count)
+        .filter_map(|i| font_offset.checked_add(12 + i * 16))
+        .find(|&r| data.get(r..).and_then(|t| t.get(..4)) == Some(b"name"))
+        .map(|r| read_u32(data, r.checked_add(8)?))?? as usize;
+
+    let record_count = read_u16(data, name_table.checked_add(2)?)? as usize;
+    let strings_offset =
+        name_table.checked_add(read_u16(data, name_table.checked_add(4)?)? as usize)?;
+
+    // Prefer the family name (1) over the typographic family name (16), and English Windows
+    // names over other platforms.
+    let mut names = (0..record_count)
+        .filter_map(|i| {
+            let record = name_table.checked_add(6 + i * 12)?;
+            let platform_id = read_u16(data, record)?;
+            let language_id = read_u16(data, record + 4)?;
+            let name_id = read_u16(data, record + 6)?;
+            let length = read_u16(data, record + 8)? as usize;
+            let offset = strings_offset.checked_add(read_u16(data, record + 10)? as usize)?;
+
+            if name_id != 1 && name_id != 16 {
+                return None;
+            }
+
+            let bytes = data.get(offset..offset.checked_add(length)?)?;
+            let name = match platform_id {
+                0 | 3 => String::from_utf16(
+                    &bytes
+                        .chunks_exact(2)
+                        .map(|c| u16::from_be_bytes([c[0], c[1]]))
+                        .collect::<Vec<_>>(),
+                )
+                .ok()?,
+                // Mac Roman, only ASCII names are read correctly.
+                1 => bytes.iter().map(|&b| b as char).collect(),
+                _ => return None,
+            };
+
+            let priority = (
+                name_id != 1,
+                platform_id != 3,
+                language_id != 0x409 && platform_id == 3,
+            );
+
+            Some((priority, name))
+        })
+        .filter(|(_, n)| !n.trim().is_empty())
+        .collect::<Vec<_>>();
+    names.sort();
+
+    names.into_iter().next().map(|(_, n)| n)
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_be_bytes(
+        data.get(offset..offset.checked_add(2)?)?.try_into().ok()?,
+    ))
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(
+        data.get(offset..offset.checked_add(4)?)?.try_into().ok()?,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A record of a `name` table: platform, language, name id, and the encoded name.
+    type NameRecord<'a> = (u16, u16, u16, &'a [u8]);
+
+    fn utf16(name: &str) -> Vec<u8> {
+        name.encode_utf16().flat_map(u16::to_be_bytes).collect()
+    }
+
+    /// Build a font with only a `name` table.
+    fn font(records: &[NameRecord]) -> Vec<u8> {
+        let mut data = 0x10000u32.to_be_bytes().to_vec(); // sfnt version
+        data.extend(1u16.to_be_bytes()); // numTables
+        data.extend([0; 6]); // searchRange, entrySelector, and rangeShift
+        data.extend_from_slice(b"name");
+        data.extend([0; 4]); // checksum
+        data.extend(28u32.to_be_bytes()); // offset
+
+        let mut name = 0u16.to_be_bytes().to_vec(); // format
+        name.extend((records.len() as u16).to_be_bytes());
+        name.extend((6 + 12 * records.len() as u16).to_be_bytes()); // storageOffset
+
+        let mut strings = vec![];
+        for (platform_id, language_id, name_id, value) in records {
+            name.extend(platform_id.to_be_bytes());
+            name.extend([0, 1]); // encodingID
+            name.extend(language_id.to_be_bytes());
+            name.extend(name_id.to_be_bytes());
+            name.extend((value.len() as u16).to_be_bytes());
+            name.extend((strings.len() as u16).to_be_bytes());
+            strings.extend_from_slice(value);
+        }
+        name.extend(strings);
+
+        data.extend((name.len() as u32).to_be_bytes()); // length
+        data.extend(name);
+
+        data
+    }
+
+    #[test]
+    fn reads_a_utf16_family_name() {
+        let data = font(&[(3, 0x409, 1, &utf16("Noto Sans Thai"))]);
+
+        assert_eq!(
+            parse_font_family_name(&data).as_deref(),
+            Some("Noto Sans Thai")
+        );
+    }
+
+    #[test]
+    fn reads_non_ascii_utf16_names() {
+        let data = font(&[(0, 0, 1, &utf16("思源黑體"))]);
+
+        assert_eq!(parse_font_family_name(&data).as_deref(), Some("思源黑體"));
+    }
+
+    #[test]
+    fn reads_mac_roman_names() {
+        let data = font(&[(1, 0, 1, b"Trebuchet MS")]);
+
+        assert_eq!(
+            parse_font_family_name(&data).as_deref(),
+            Some("Trebuchet MS")
+        );
+    }
+
+    #[test]
+    fn prefers_english_windows_family_names() {
+        let japanese = utf16("ノト");
+        let typographic = utf16("Noto Sans Typographic");
+        let english = utf16("Noto Sans");
+        let data = font(&[
+            (1, 0, 1, b"Noto Mac"),
+            (3, 0x411, 1, &japanese),
+            (3, 0x409, 16, &typographic),
+            (3, 0x409, 1, &english),
+            (3, 0x409, 4, b"\0F\0u\0l\0l"),
+        ]);
+
+        assert_eq!(parse_font_family_name(&data).as_deref(), Some("Noto Sans"));
+    }
+
+    #[test]
+    fn skips_empty_and_other_names() {
+        let blank = utf16("  ");
+        let full_name = utf16("Noto Sans Bold");
+        let data = font(&[(3, 0x409, 1, &blank), (3, 0x409, 4, &full_name)]);
+
+        assert_eq!(parse_font_family_name(&data), None);
+    }
+
+    #[test]
+    fn reads_the_first_font_of_a_collection() {
+        let mut data = b"ttcf".to_vec();
+        data.extend(0x10000u32.to_be_bytes()); // version
+        data.extend(1u32.to_be_bytes()); // numFonts
+        data.extend(16u32.to_be_bytes()); // offset of the first font
+
+        // Offsets inside a collection are from the start of the file.
+        let mut font = font(&[(3, 0x409, 1, &utf16("Noto Sans CJK"))]);
+        font[20..24].copy_from_slice(&(16 + 28u32).to_be_bytes());
+        data.extend(font);
+
+        assert_eq!(
+            parse_font_family_name(&data).as_deref(),
+            Some("Noto Sans CJK")
+        );
+    }
+
+    #[test]
+    fn truncated_fonts_return_none() {
+        let data = font(&[(3, 0x409, 1, &utf16("Noto Sans"))]);
+
+        for length in 0..data.len() {
+            assert_eq!(parse_font_family_name(&data[..length]), None, "{length}");
+        }
+    }
+
+    #[test]
+    fn out_of_range_offsets_return_none() {
+        let data = font(&[(3, 0x409, 1, &utf16("Noto Sans"))]);
+
+        // Offset of the `name` table.
+        let mut table_offset = data.clone();
+        table_offset[20..24].copy_from_slice(&u32::MAX.to_be_bytes());
+        // Offset of the strings.
+        let mut storage_offset = data.clone();
+        storage_offset[32..34].copy_from_slice(&u16::MAX.to_be_bytes());
+        // Offset and length of the record.
+        let mut record_offset = data.clone();
+        record_offset[44..46].copy_from_slice(&u16::MAX.to_be_bytes());
+        let mut record_length = data.clone();
+        record_length[42..44].copy_from_slice(&u16::MAX.to_be_bytes());
+        // Number of records and tables.
+        let mut record_count = data.clone();
+        record_count[30..32].copy_from_slice(&u16::MAX.to_be_bytes());
+        let mut table_count = data.clone();
+        table_count[4..6].copy_from_slice(&u16::MAX.to_be_bytes());
+        table_count[12..16].copy_from_slice(b"cmap");
+        // Offset of the first font of a collection.
+        let collection = [&b"ttcf"[..], &[0; 8], &u32::MAX.to_be_bytes()].concat();
+
+        for data in [
+            table_offset,
+            storage_offset,
+            record_offset,
+            record_length,
+            table_count,
+            collection,
+        ] {
+            assert_eq!(parse_font_family_name(&data), None);
+        }
+
+        // Records past the end are skipped, and the valid one is still read.
+        assert_eq!(
+            parse_font_family_name(&record_count).as_deref(),
+            Some("Noto Sans")
+        );
+    }
+
+    #[test]
+    fn odd_utf16_lengths_are_read_without_the_last_byte() {
+        let mut name = utf16("Noto");
+        name.push(0);
+        let data = font(&[(3, 0x409, 1, &name)]);
+
+        assert_eq!(parse_font_family_name(&data).as_deref(), Some("Noto"));
+    }
+
+    #[test]
+    fn unreadable_fonts_are_input_errors() {
+        let path = std::env::temp_dir().join(format!(
+            "bilibili-extractor-{}-missing.ttf",
+            std::process::id()
+        ));
+
+        assert!(matches!(
+            get_font_family_name(path),
+            Err(Error::InputError(_))
+        ));
+    }
+}
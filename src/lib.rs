@@ -1,5 +1,14 @@
 pub mod combiner;
+pub mod compiler;
+pub mod danmaku;
 pub mod error;
+pub mod event;
+pub mod font;
 pub mod metadata;
+pub mod muxer;
 pub mod packager;
+pub mod report;
 pub mod subtitle;
+
+mod matroska;
+mod mp4;
@@ -0,0 +1,1094 @@
+use crate::{
+    error::{Error, Result},
+    font::get_font_media_type,
+    mp4::{Mp4Codec, Mp4Track, Mp4TrackKind},
+    muxer::MuxJob,
+    subtitle::get_iso_639_2_language,
+};
+use std::{
+    fs::{read, read_to_string, File},
+    io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+const EBML: u32 = 0x1A45DFA3;
+const EBML_VERSION: u32 = 0x4286;
+const EBML_READ_VERSION: u32 = 0x42F7;
+const EBML_MAX_ID_LENGTH: u32 = 0x42F2;
+const EBML_MAX_SIZE_LENGTH: u32 = 0x42F3;
+const DOC_TYPE: u32 = 0x4282;
+const DOC_TYPE_VERSION: u32 = 0x4287;
+const DOC_TYPE_READ_VERSION: u32 = 0x4285;
+const SEGMENT: u32 = 0x18538067;
+const SEEK_HEAD: u32 = 0x114D9B74;
+const SEEK: u32 = 0x4DBB;
+const SEEK_ID: u32 = 0x53AB;
+const SEEK_POSITION: u32 = 0x53AC;
+const INFO: u32 = 0x1549A966;
+const TIMESTAMP_SCALE: u32 = 0x2AD7B1;
+const DURATION: u32 = 0x4489;
+const TITLE: u32 = 0x7BA9;
+const MUXING_APP: u32 = 0x4D80;
+const WRITING_APP: u32 = 0x5741;
+const TRACKS: u32 = 0x1654AE6B;
+const TRACK_ENTRY: u32 = 0xAE;
+const TRACK_NUMBER: u32 = 0xD7;
+const TRACK_UID: u32 = 0x73C5;
+const TRACK_TYPE: u32 = 0x83;
+const FLAG_DEFAULT: u32 = 0x88;
+const FLAG_LACING: u32 = 0x9C;
+const NAME: u32 = 0x536E;
+const LANGUAGE: u32 = 0x22B59C;
+const CODEC_ID: u32 = 0x86;
+const CODEC_PRIVATE: u32 = 0x63A2;
+const VIDEO: u32 = 0xE0;
+const PIXEL_WIDTH: u32 = 0xB0;
+const PIXEL_HEIGHT: u32 = 0xBA;
+const AUDIO: u32 = 0xE1;
+const SAMPLING_FREQUENCY: u32 = 0xB5;
+const CHANNELS: u32 = 0x9F;
+const CLUSTER: u32 = 0x1F43B675;
+const TIMESTAMP: u32 = 0xE7;
+const SIMPLE_BLOCK: u32 = 0xA3;
+const BLOCK_GROUP: u32 = 0xA0;
+const BLOCK: u32 = 0xA1;
+const BLOCK_DURATION: u32 = 0x9B;
+const CUES: u32 = 0x1C53BB6B;
+const CUE_POINT: u32 = 0xBB;
+const CUE_TIME: u32 = 0xB3;
+const CUE_TRACK_POSITIONS: u32 = 0xB7;
+const CUE_TRACK: u32 = 0xF7;
+const CUE_CLUSTER_POSITION: u32 = 0xF1;
+const ATTACHMENTS: u32 = 0x1941A469;
+const ATTACHED_FILE: u32 = 0x61A7;
+const FILE_NAME: u32 = 0x466E;
+const FILE_MEDIA_TYPE: u32 = 0x4660;
+const FILE_DATA: u32 = 0x465C;
+const FILE_UID: u32 = 0x46AE;
+
+const VIDEO_TRACK_NUMBER: u64 = 1;
+const AUDIO_TRACK_NUMBER: u64 = 2;
+const FIRST_SUBTITLE_TRACK_NUMBER: u64 = 3;
+
+/// A parsed ASS subtitle, split into the Matroska `CodecPrivate` header and its events.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct AssTrack {
+    pub header: String,
+    pub events: Vec<AssEvent>,
+}
+
+/// A dialogue line of an ASS subtitle, already in Matroska block format.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct AssEvent {
+    pub start: i64,
+    pub end: i64,
+    pub data: String,
+}
+
+/// A block to be written, in milliseconds.
+#[derive(Debug, Clone, Copy)]
+struct PendingBlock {
+    track_number: u64,
+    decode_time: i64,
+    presentation_time: i64,
+    source: BlockSource,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum BlockSource {
+    Video { sample: usize },
+    Audio { sample: usize },
+    Subtitle { track: usize, event: usize },
+}
+
+/// Reads samples from an MP4 file, only seeking when samples aren't contiguous.
+struct SampleReader {
+    reader: BufReader<File>,
+    position: u64,
+    file_size: u64,
+}
+
+/// Writes EBML elements into a buffer.
+trait EbmlWriter {
+    fn ebml_id(&mut self, id: u32);
+    fn ebml_size(&mut self, size: u64);
+    fn ebml_binary(&mut self, id: u32, value: &[u8]);
+    fn ebml_uint(&mut self, id: u32, value: u64);
+    fn ebml_float(&mut self, id: u32, value: f64);
+    fn ebml_string(&mut self, id: u32, value: &str);
+    fn ebml_master(&mut self, id: u32, children: impl FnOnce(&mut Vec<u8>));
+}
+
+impl EbmlWriter for Vec<u8> {
+    fn ebml_id(&mut self, id: u32) {
+        let bytes = id.to_be_bytes();
+        let leading_zeros = (id.leading_zeros() / 8) as usize;
+
+        self.extend_from_slice(&bytes[leading_zeros.min(3)..]);
+    }
+
+    fn ebml_size(&mut self, size: u64) {
+        let length = (1..=8).find(|l| size < (1 << (7 * l)) - 1).unwrap_or(8);
+        let bytes = (size | (1 << (7 * length))).to_be_bytes();
+
+        self.extend_from_slice(&bytes[8 - length..]);
+    }
+
+    fn ebml_binary(&mut self, id: u32, value: &[u8]) {
+        self.ebml_id(id);
+        self.ebml_size(value.len() as u64);
+        self.extend_from_slice(value);
+    }
+
+    fn ebml_uint(&mut self, id: u32, value: u64) {
+        let bytes = value.to_be_bytes();
+        let leading_zeros = ((value.leading_zeros() / 8) as usize).min(7);
+
+        self.ebml_binary(id, &bytes[leading_zeros..]);
+    }
+
+    fn ebml_float(&mut self, id: u32, value: f64) {
+        self.ebml_binary(id, &value.to_be_bytes());
+    }
+
+    fn ebml_string(&mut self, id: u32, value: &str) {
+        self.ebml_binary(id, value.as_bytes());
+    }
+
+    fn ebml_master(&mut self, id: u32, children: impl FnOnce(&mut Vec<u8>)) {
+        let mut buffer = vec![];
+        children(&mut buffer);
+
+        self.ebml_binary(id, &buffer);
+    }
+}
+
+/// Write the video, audio, and subtitles of a job as a Matroska file.
+pub(crate) fn write_matroska(job: &MuxJob) -> Result<()> {
+    let video = find_track(&job.video_path, |k| matches!(k, Mp4TrackKind::Video { .. }))?;
+    let audio = find_track(&job.audio_path, |k| matches!(k, Mp4TrackKind::Audio { .. }))?;
+    let subtitles = job
+        .subtitle_tracks
+        .iter()
+        .map(|t| AssTrack::new_from_path(&t.path))
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut blocks = get_pending_blocks(&video, &audio, &subtitles);
+    blocks.sort_by_key(|b| (b.decode_time, b.track_number));
+
+    let duration = blocks
+        .iter()
+        .map(|b| match b.source {
+            BlockSource::Video { sample } => {
+                b.presentation_time + to_milliseconds(video.samples[sample].duration as i64, &video)
+            }
+            BlockSource::Audio { sample } => {
+                b.presentation_time + to_milliseconds(audio.samples[sample].duration as i64, &audio)
+            }
+            BlockSource::Subtitle { track, event } => subtitles[track].events[event].end,
+        })
+        .max()
+        .unwrap_or_default();
+
+    let mut output = BufWriter::new(File::create(&job.output_path)?);
+
+    let mut header = vec![];
+    header.ebml_master(EBML, |b| {
+        b.ebml_uint(EBML_VERSION, 1);
+        b.ebml_uint(EBML_READ_VERSION, 1);
+        b.ebml_uint(EBML_MAX_ID_LENGTH, 4);
+        b.ebml_uint(EBML_MAX_SIZE_LENGTH, 8);
+        b.ebml_string(DOC_TYPE, "matroska");
+        b.ebml_uint(DOC_TYPE_VERSION, 4);
+        b.ebml_uint(DOC_TYPE_READ_VERSION, 2);
+    });
+    header.ebml_id(SEGMENT);
+    output.write_all(&header)?;
+
+    // The size of the segment is only known at the end, so reserve 8 bytes for it.
+    let segment_size_position = output.stream_position()?;
+    output.write_all(&[0x01, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF])?;
+    let segment_start = output.stream_position()?;
+
+    // Seek positions are patched once every element is written.
+    let seek_ids = match job.attachments.is_empty() {
+        true => vec![INFO, TRACKS, CUES],
+        false => vec![INFO, TRACKS, ATTACHMENTS, CUES],
+    };
+    let mut seek_head = vec![];
+    let mut seek_position_offsets = vec![];
+    seek_head.ebml_master(SEEK_HEAD, |b| {
+        for &id in &seek_ids {
+            let mut id_bytes = vec![];
+            id_bytes.ebml_id(id);
+
+            b.ebml_id(SEEK);
+            b.ebml_size(id_bytes.len() as u64 + 3 + 11);
+            b.ebml_binary(SEEK_ID, &id_bytes);
+            b.ebml_id(SEEK_POSITION);
+            b.ebml_size(8);
+            seek_position_offsets.push(b.len());
+            b.extend_from_slice(&[0; 8]);
+        }
+    });
+    // `ebml_master` prepends the id and size of the seek head.
+    let seek_head_header_size = seek_head.len() - seek_position_offsets.last().unwrap_or(&0) - 8;
+    output.write_all(&seek_head)?;
+
+    let info_position = output.stream_position()? - segment_start;
+    let mut info = vec![];
+    info.ebml_master(INFO, |b| {
+        b.ebml_uint(TIMESTAMP_SCALE, 1_000_000);
+        b.ebml_string(MUXING_APP, "bilibili-extractor");
+        b.ebml_string(WRITING_APP, "bilibili-extractor");
+        b.ebml_string(TITLE, &job.title);
+        b.ebml_float(DURATION, duration as f64);
+    });
+    output.write_all(&info)?;
+
+    let tracks_position = output.stream_position()? - segment_start;
+    let mut tracks = vec![];
+    tracks.ebml_master(TRACKS, |b| {
+        write_mp4_track_entry(b, VIDEO_TRACK_NUMBER, &video);
+        write_mp4_track_entry(b, AUDIO_TRACK_NUMBER, &audio);
+
+        for (i, (subtitle, subtitle_track)) in
+            subtitles.iter().zip(&job.subtitle_tracks).enumerate()
+        {
+            b.ebml_master(TRACK_ENTRY, |b| {
+                b.ebml_uint(TRACK_NUMBER, FIRST_SUBTITLE_TRACK_NUMBER + i as u64);
+                b.ebml_uint(TRACK_UID, FIRST_SUBTITLE_TRACK_NUMBER + i as u64);
+                b.ebml_uint(TRACK_TYPE, 17);
+                b.ebml_uint(FLAG_DEFAULT, (i == job.default_track) as u64);
+                b.ebml_uint(FLAG_LACING, 0);
+                b.ebml_string(NAME, subtitle_track.get_title());
+                b.ebml_string(LANGUAGE, get_iso_639_2_language(&subtitle_track.language));
+                b.ebml_string(CODEC_ID, "S_TEXT/ASS");
+                b.ebml_binary(CODEC_PRIVATE, subtitle.header.as_bytes());
+            });
+        }
+    });
+    output.write_all(&tracks)?;
+
+    let mut seek_positions = vec![info_position, tracks_position];
+
+    if !job.attachments.is_empty() {
+        seek_positions.push(output.stream_position()? - segment_start);
+        write_attachments(&mut output, &job.attachments)?;
+    }
+
+    let mut video_reader = SampleReader::new(&job.video_path)?;
+    let mut audio_reader = SampleReader::new(&job.audio_path)?;
+    let mut cues = vec![];
+    let mut cluster: Option<(i64, Vec<u8>)> = None;
+
+    for block in blocks {
+        let is_keyframe = match block.source {
+            BlockSource::Video { sample } => video.samples[sample].is_keyframe,
+            _ => true,
+        };
+        let starts_cluster = block.track_number == VIDEO_TRACK_NUMBER && is_keyframe;
+
+        let relative_time = match &cluster {
+            Some((cluster_time, _)) if !starts_cluster => {
+                i16::try_from(block.presentation_time - cluster_time).ok()
+            }
+            _ => None,
+        };
+        let relative_time = match relative_time {
+            Some(t) => t,
+            None => {
+                if let Some((_, c)) = cluster.take() {
+                    write_cluster(&mut output, &c)?;
+                }
+
+                let cluster_position = output.stream_position()? - segment_start;
+                if starts_cluster {
+                    cues.push((block.presentation_time.max(0) as u64, cluster_position));
+                }
+
+                let mut c = vec![];
+                c.ebml_uint(TIMESTAMP, block.presentation_time.max(0) as u64);
+                cluster = Some((block.presentation_time.max(0), c));
+
+                (block.presentation_time - block.presentation_time.max(0)) as i16
+            }
+        };
+
+        let (_, c) = cluster.as_mut().ok_or("Cluster wasn't created.")?;
+        let mut block_header = vec![0x80 | block.track_number as u8];
+        block_header.extend_from_slice(&relative_time.to_be_bytes());
+
+        match block.source {
+            BlockSource::Video { sample } => {
+                let sample = &video.samples[sample];
+                block_header.push(if is_keyframe { 0x80 } else { 0 });
+                block_header.extend(video_reader.read(sample.offset, sample.size)?);
+
+                c.ebml_binary(SIMPLE_BLOCK, &block_header);
+            }
+            BlockSource::Audio { sample } => {
+                let sample = &audio.samples[sample];
+                block_header.push(0x80);
+                block_header.extend(audio_reader.read(sample.offset, sample.size)?);
+
+                c.ebml_binary(SIMPLE_BLOCK, &block_header);
+            }
+            BlockSource::Subtitle { track, event } => {
+                let event = &subtitles[track].events[event];
+                block_header.push(0);
+                block_header.extend_from_slice(event.data.as_bytes());
+
+                c.ebml_master(BLOCK_GROUP, |b| {
+                    b.ebml_binary(BLOCK, &block_header);
+                    b.ebml_uint(BLOCK_DURATION, (event.end - event.start).max(0) as u64);
+                });
+            }
+        }
+    }
+
+    if let Some((_, c)) = cluster.take() {
+        write_cluster(&mut output, &c)?;
+    }
+
+    seek_positions.push(output.stream_position()? - segment_start);
+    let mut cues_element = vec![];
+    cues_element.ebml_master(CUES, |b| {
+        for (time, position) in &cues {
+            b.ebml_master(CUE_POINT, |b| {
+                b.ebml_uint(CUE_TIME, *time);
+                b.ebml_master(CUE_TRACK_POSITIONS, |b| {
+                    b.ebml_uint(CUE_TRACK, VIDEO_TRACK_NUMBER);
+                    b.ebml_uint(CUE_CLUSTER_POSITION, *position);
+                });
+            });
+        }
+    });
+    output.write_all(&cues_element)?;
+
+    let segment_end = output.stream_position()?;
+
+    output.seek(SeekFrom::Start(segment_size_position + 1))?;
+    output.write_all(&(segment_end - segment_start).to_be_bytes()[1..])?;
+
+    for (offset, position) in seek_position_offsets.iter().zip(seek_positions) {
+        output.seek(SeekFrom::Start(
+            segment_start + (seek_head_header_size + offset) as u64,
+        ))?;
+        output.write_all(&position.to_be_bytes())?;
+    }
+
+    output.flush()?;
+
+    Ok(())
+}
+
+/// Write every file as an attachment, e.g. fonts used by the subtitles.
+fn write_attachments(output: &mut impl Write, paths: &[PathBuf]) -> Result<()> {
+    let mut attachments = vec![];
+
+    for (i, path) in paths.iter().enumerate() {
+        let data = read(path)?;
+        let file_name = path
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_default();
+
+        attachments.ebml_master(ATTACHED_FILE, |b| {
+            b.ebml_string(FILE_NAME, &file_name);
+            b.ebml_string(FILE_MEDIA_TYPE, get_font_media_type(path));
+            b.ebml_binary(FILE_DATA, &data);
+            b.ebml_uint(FILE_UID, i as u64 + 1);
+        });
+    }
+
+    let mut element = vec![];
+    element.ebml_binary(ATTACHMENTS, &attachments);
+    output.write_all(&element)?;
+
+    Ok(())
+}
+
+fn write_cluster(output: &mut impl Write, content: &[u8]) -> Result<()> {
+    let mut cluster = vec![];
+    cluster.ebml_binary(CLUSTER, content);
+    output.write_all(&cluster)?;
+
+    Ok(())
+}
+
+/// Find the first track of a kind inside an MP4 file.
+fn find_track(path: &Path, is_kind: impl Fn(&Mp4TrackKind) -> bool) -> Result<Mp4Track> {
+    Mp4Track::read_tracks(path)?
+        .into_iter()
+        .find(|t| is_kind(&t.kind))
+        .ok_or(format!("No matching track found in {}.", path.display()).into())
+}
+
+fn write_mp4_track_entry(buffer: &mut Vec<u8>, track_number: u64, track: &Mp4Track) {
+    let (codec_id, codec_private) = match &track.codec {
+        Mp4Codec::Avc(c) => ("V_MPEG4/ISO/AVC", Some(c)),
+        Mp4Codec::Hevc(c) => ("V_MPEGH/ISO/HEVC", Some(c)),
+        Mp4Codec::Av1(c) => ("V_AV1", Some(c)),
+        Mp4Codec::Aac(c) => ("A_AAC", Some(c)),
+        Mp4Codec::Ac3 => ("A_AC3", None),
+        Mp4Codec::Eac3 => ("A_EAC3", None),
+        Mp4Codec::Flac(c) => ("A_FLAC", Some(c)),
+    };
+
+    buffer.ebml_master(TRACK_ENTRY, |b| {
+        b.ebml_uint(TRACK_NUMBER, track_number);
+        b.ebml_uint(TRACK_UID, track_number);
+        b.ebml_uint(FLAG_LACING, 0);
+        b.ebml_string(LANGUAGE, "und");
+        b.ebml_string(CODEC_ID, codec_id);
+        if let Some(c) = codec_private {
+            b.ebml_binary(CODEC_PRIVATE, c);
+        }
+
+        match track.kind {
+            Mp4TrackKind::Video { width, height } => {
+                b.ebml_uint(TRACK_TYPE, 1);
+                b.ebml_master(VIDEO, |b| {
+                    b.ebml_uint(PIXEL_WIDTH, width as u64);
+                    b.ebml_uint(PIXEL_HEIGHT, height as u64);
+                });
+            }
+            Mp4TrackKind::Audio {
+                sample_rate,
+                channels,
+            } => {
+                b.ebml_uint(TRACK_TYPE, 2);
+                b.ebml_master(AUDIO, |b| {
+                    b.ebml_float(SAMPLING_FREQUENCY, sample_rate);
+                    b.ebml_uint(CHANNELS, channels as u64);
+                });
+            }
+        }
+    });
+}
+
+/// Get every block of every track, in milliseconds. Times are shifted so that nothing is
+/// presented before zero.
+fn get_pending_blocks(
+    video: &Mp4Track,
+    audio: &Mp4Track,
+    subtitles: &[AssTrack],
+) -> Vec<PendingBlock> {
+    let mut blocks = vec![];
+
+    for (track_number, track) in [(VIDEO_TRACK_NUMBER, video), (AUDIO_TRACK_NUMBER, audio)] {
+        for (i, s) in track.samples.iter().enumerate() {
+            blocks.push(PendingBlock {
+                track_number,
+                decode_time: to_milliseconds(s.decode_time - track.media_time, track),
+                presentation_time: to_milliseconds(
+                    s.get_presentation_time() - track.media_time,
+                    track,
+                ),
+                source: match track_number {
+                    VIDEO_TRACK_NUMBER => BlockSource::Video { sample: i },
+                    _ => BlockSource::Audio { sample: i },
+                },
+            })
+        }
+    }
+
+    for (i, subtitle) in subtitles.iter().enumerate() {
+        for (j, e) in subtitle.events.iter().enumerate() {
+            blocks.push(PendingBlock {
+                track_number: FIRST_SUBTITLE_TRACK_NUMBER + i as u64,
+                decode_time: e.start,
+                presentation_time: e.start,
+                source: BlockSource::Subtitle { track: i, event: j },
+            })
+        }
+    }
+
+    let offset = blocks
+        .iter()
+        .map(|b| b.presentation_time)
+        .min()
+        .unwrap_or_default()
+        .min(0);
+    blocks.iter_mut().for_each(|b| {
+        b.decode_time -= offset;
+        b.presentation_time -= offset;
+    });
+
+    blocks
+}
+
+fn to_milliseconds(time: i64, track: &Mp4Track) -> i64 {
+    (time as f64 * 1000. / track.timescale.max(1) as f64).round() as i64
+}
+
+impl SampleReader {
+    fn new(path: &Path) -> Result<Self> {
+        let file = File::open(path)?;
+        let file_size = file.metadata()?.len();
+
+        Ok(Self {
+            reader: BufReader::new(file),
+            position: 0,
+            file_size,
+        })
+    }
+
+    fn read(&mut self, offset: u64, size: u32) -> Result<Vec<u8>> {
+        if offset.saturating_add(size as u64) > self.file_size {
+            return Err(Error::InputError(format!(
+                "MP4 sample at {offset} with {size} bytes is past the end of the file."
+            )));
+        }
+
+        if offset != self.position {
+            self.reader.seek(SeekFrom::Start(offset))?;
+        }
+
+        let mut data = vec![0; size as usize];
+        self.reader.read_exact(&mut data)?;
+        self.position = offset + size as u64;
+
+        Ok(data)
+    }
+}
+
+impl AssTrack {
+    /// Read an ASS file.
+    pub fn new_from_path(path: impl AsRef<Path>) -> Result<Self> {
+        Self::parse(&read_to_string(path)?)
+    }
+
+    /// Parse an ASS subtitle. Dialogue lines become events, everything before them becomes the
+    /// header.
+    pub fn parse(ass: &str) -> Result<Self> {
+        let mut header = String::new();
+        let mut events = vec![];
+        let mut format: Vec<String> = vec![];
+        let mut section = String::new();
+
+        for line in ass.trim_start_matches('\u{feff}').lines() {
+            let line = line.trim_end_matches('\r');
+
+            if line.starts_with('[') {
+                section = line.trim().to_lowercase();
+            }
+
+            match section.as_str() {
+                "[events]" => {}
+                "[script info]" | "[v4+ styles]" | "[v4 styles]" => {
+                    header.push_str(line);
+                    header.push('\n');
+                    continue;
+                }
+                // Fonts and graphics are attachments in Matroska.
+                _ => continue,
+            }
+
+            if line.starts_with('[') {
+                header.push_str("[Events]\n");
+            } else if let Some(f) = line.strip_prefix("Format:") {
+                format = f.split(',').map(|f| f.trim().to_lowercase()).collect();
+                header.push_str(line);
+                header.push('\n');
+            } else if let Some(d) = line.strip_prefix("Dialogue:") {
+                if format.is_empty() {
+                    return Err("ASS events have no format line.".into());
+                }
+
+                let fields: Vec<&str> = d.trim_start().splitn(format.len(), ',').collect();
+                let get_field = |name: &str| {
+                    format
+                        .iter()
+                        .position(|f| f == name)
+                        .and_then(|i| fields.get(i))
+                        .map(|f| f.trim())
+                };
+
+                let start = parse_ass_time(get_field("start").ok_or("ASS event has no start.")?)?;
+                let end = parse_ass_time(get_field("end").ok_or("ASS event has no end.")?)?;
+                let data = format!(
+                    "{},{},{},{},{},{},{},{},{}",
+                    events.len(),
+                    get_field("layer").unwrap_or("0"),
+                    get_field("style").unwrap_or("Default"),
+                    get_field("name").unwrap_or_default(),
+                    get_field("marginl").unwrap_or("0"),
+                    get_field("marginr").unwrap_or("0"),
+                    get_field("marginv").unwrap_or("0"),
+                    get_field("effect").unwrap_or_default(),
+                    // The text may contain commas, so it's never trimmed.
+                    fields
+                        .last()
+                        .filter(|_| fields.len() == format.len())
+                        .unwrap_or(&"")
+                );
+
+                events.push(AssEvent { start, end, data });
+            }
+        }
+
+        Ok(Self { header, events })
+    }
+}
+
+/// Parse an ASS time, e.g. `0:01:02.50`, into milliseconds.
+fn parse_ass_time(time: &str) -> Result<i64> {
+    let invalid_time = || format!("Invalid ASS time: {time}");
+    let mut parts = time.split(':');
+    let hours: i64 = parts.next().ok_or_else(invalid_time)?.parse()?;
+    let minutes: i64 = parts.next().ok_or_else(invalid_time)?.parse()?;
+    let seconds = parts.next().ok_or_else(invalid_time)?;
+    let (seconds, fraction) = seconds.split_once('.').unwrap_or((seconds, ""));
+    let seconds: i64 = seconds.parse()?;
+    let milliseconds = match fraction.is_empty() {
+        true => 0,
+        false => (fraction.parse::<f64>().map_err(|_| invalid_time())? * 1000.
+            / 10f64.powi(fraction.len() as i32))
+        .round() as i64,
+    };
+
+    Ok(((hours * 60 + minutes) * 60 + seconds) * 1000 + milliseconds)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        combiner::SubtitleTrack,
+        mp4::{
+            tests::{fragmented_mp4, get_temporary_path, TestSample, AAC_CONFIG, AVC_CONFIG},
+            Mp4Sample,
+        },
+        subtitle::SubtitleType,
+    };
+    use std::fs;
+
+    const MASTER_IDS: &[u32] = &[
+        EBML,
+        SEGMENT,
+        SEEK_HEAD,
+        SEEK,
+        INFO,
+        TRACKS,
+        TRACK_ENTRY,
+        VIDEO,
+        AUDIO,
+        CLUSTER,
+        BLOCK_GROUP,
+        CUES,
+        CUE_POINT,
+        CUE_TRACK_POSITIONS,
+        ATTACHMENTS,
+        ATTACHED_FILE,
+    ];
+
+    const ASS: &str = "[Script Info]\r
+ScriptType: v4.00+\r
+\r
+[V4+ Styles]\r
+Format: Name, Fontname, Fontsize\r
+Style: Default,Arial,20\r
+\r
+[Events]\r
+Format: Layer, Start, End, Style, Name, MarginL, MarginR, MarginV, Effect, Text\r
+Dialogue: 0,0:00:00.05,0:00:01.00,Default,,0,0,0,,Hello, world\r
+Comment: 0,0:00:00.00,0:00:01.00,Default,,0,0,0,,Ignored\r
+Dialogue: 1,0:01:02.50,1:00:00.00,Top,Speaker,10,20,30,Banner,{\\an8}Second\r
+";
+
+    /// An element read back from EBML. Master elements have children instead of data.
+    #[derive(Debug)]
+    struct Element {
+        id: u32,
+        data: Vec<u8>,
+        children: Vec<Element>,
+    }
+
+    impl Element {
+        fn find(&self, id: u32) -> &Element {
+            self.children
+                .iter()
+                .find(|e| e.id == id)
+                .unwrap_or_else(|| panic!("No element {id:X} in {:X}", self.id))
+        }
+
+        fn find_all(&self, id: u32) -> impl Iterator<Item = &Element> {
+            self.children.iter().filter(move |e| e.id == id)
+        }
+
+        fn uint(&self) -> u64 {
+            self.data.iter().fold(0, |v, b| v << 8 | *b as u64)
+        }
+
+        fn string(&self) -> &str {
+            std::str::from_utf8(&self.data).unwrap()
+        }
+    }
+
+    /// Read a variable length integer. Returns the value and its length.
+    fn read_vint(data: &[u8], keep_marker: bool) -> (u64, usize) {
+        let length = data[0].leading_zeros() as usize + 1;
+        let value = data[..length].iter().fold(0, |v, b| v << 8 | *b as u64);
+
+        match keep_marker {
+            true => (value, length),
+            false => (value & ((1 << (7 * length)) - 1), length),
+        }
+    }
+
+    fn read_elements(mut data: &[u8]) -> Vec<Element> {
+        let mut elements = vec![];
+
+        while !data.is_empty() {
+            let (id, id_length) = read_vint(data, true);
+            let (size, size_length) = read_vint(&data[id_length..], false);
+            let start = id_length + size_length;
+            let payload = &data[start..start + size as usize];
+
+            elements.push(Element {
+                id: id as u32,
+                data: payload.to_vec(),
+                children: match MASTER_IDS.contains(&(id as u32)) {
+                    true => read_elements(payload),
+                    false => vec![],
+                },
+            });
+            data = &data[start + size as usize..];
+        }
+
+        elements
+    }
+
+    fn encode(write: impl FnOnce(&mut Vec<u8>)) -> Vec<u8> {
+        let mut buffer = vec![];
+        write(&mut buffer);
+
+        buffer
+    }
+
+    #[test]
+    fn ebml_id_keeps_the_length_marker() {
+        assert_eq!(encode(|b| b.ebml_id(SIMPLE_BLOCK)), vec![0xA3]);
+        assert_eq!(encode(|b| b.ebml_id(EBML_VERSION)), vec![0x42, 0x86]);
+        assert_eq!(encode(|b| b.ebml_id(LANGUAGE)), vec![0x22, 0xB5, 0x9C]);
+        assert_eq!(encode(|b| b.ebml_id(EBML)), vec![0x1A, 0x45, 0xDF, 0xA3]);
+    }
+
+    #[test]
+    fn ebml_size_uses_the_shortest_length() {
+        assert_eq!(encode(|b| b.ebml_size(0)), vec![0x80]);
+        assert_eq!(encode(|b| b.ebml_size(126)), vec![0xFE]);
+        // All ones are reserved for unknown sizes.
+        assert_eq!(encode(|b| b.ebml_size(127)), vec![0x40, 0x7F]);
+        assert_eq!(encode(|b| b.ebml_size(16382)), vec![0x7F, 0xFE]);
+        assert_eq!(encode(|b| b.ebml_size(16383)), vec![0x20, 0x3F, 0xFF]);
+        assert_eq!(encode(|b| b.ebml_size(1 << 40)), vec![0x05, 0, 0, 0, 0, 0]);
+
+        for size in [0, 1, 126, 127, 128, 16383, 1 << 20, (1 << 56) - 2] {
+            assert_eq!(read_vint(&encode(|b| b.ebml_size(size)), false).0, size);
+        }
+    }
+
+    #[test]
+    fn ebml_uint_drops_leading_zero_bytes() {
+        assert_eq!(
+            encode(|b| b.ebml_uint(TRACK_NUMBER, 0)),
+            vec![0xD7, 0x81, 0]
+        );
+        assert_eq!(
+            encode(|b| b.ebml_uint(TRACK_NUMBER, 255)),
+            vec![0xD7, 0x81, 0xFF]
+        );
+        assert_eq!(
+            encode(|b| b.ebml_uint(TRACK_NUMBER, 256)),
+            vec![0xD7, 0x82, 0x01, 0]
+        );
+        assert_eq!(
+            encode(|b| b.ebml_uint(TIMESTAMP_SCALE, u64::MAX)),
+            [&[0x2A, 0xD7, 0xB1, 0x88][..], &[0xFF; 8]].concat()
+        );
+    }
+
+    #[test]
+    fn ebml_master_wraps_its_children() {
+        let element = encode(|b| {
+            b.ebml_master(VIDEO, |b| {
+                b.ebml_uint(PIXEL_WIDTH, 640);
+                b.ebml_string(NAME, "ok");
+            })
+        });
+
+        assert_eq!(
+            element,
+            vec![0xE0, 0x89, 0xB0, 0x82, 0x02, 0x80, 0x53, 0x6E, 0x82, b'o', b'k']
+        );
+    }
+
+    #[test]
+    fn parse_ass_time_reads_every_precision() {
+        assert_eq!(parse_ass_time("0:00:00.00").unwrap(), 0);
+        assert_eq!(parse_ass_time("0:01:02.50").unwrap(), 62_500);
+        assert_eq!(parse_ass_time("1:00:00.05").unwrap(), 3_600_050);
+        assert_eq!(parse_ass_time("0:00:01.125").unwrap(), 1125);
+        assert_eq!(parse_ass_time("0:00:01.5").unwrap(), 1500);
+        assert_eq!(parse_ass_time("0:00:07").unwrap(), 7000);
+        assert_eq!(parse_ass_time("10:00:00.00").unwrap(), 36_000_000);
+
+        assert!(parse_ass_time("0:01").is_err());
+        assert!(parse_ass_time("a:00:00.00").is_err());
+        assert!(parse_ass_time("0:00:00.x").is_err());
+    }
+
+    #[test]
+    fn ass_track_splits_the_header_and_events() {
+        let track = AssTrack::parse(ASS).unwrap();
+
+        assert!(track
+            .header
+            .starts_with("[Script Info]\nScriptType: v4.00+\n"));
+        assert!(track.header.contains("Style: Default,Arial,20\n"));
+        assert!(track.header.ends_with(
+            "[Events]\nFormat: Layer, Start, End, Style, Name, MarginL, MarginR, MarginV, \
+             Effect, Text\n"
+        ));
+        assert!(!track.header.contains("Dialogue"));
+        assert_eq!(
+            track.events,
+            vec![
+                AssEvent {
+                    start: 50,
+                    end: 1000,
+                    data: "0,0,Default,,0,0,0,,Hello, world".into(),
+                },
+                AssEvent {
+                    start: 62_500,
+                    end: 3_600_000,
+                    data: "1,1,Top,Speaker,10,20,30,Banner,{\\an8}Second".into(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn ass_track_requires_a_format_line() {
+        assert!(
+            AssTrack::parse("[Events]\nDialogue: 0,0:00:00.00,0:00:01.00,,,0,0,0,,Hi").is_err()
+        );
+    }
+
+    #[test]
+    fn get_pending_blocks_applies_the_edit_list() {
+        let sample = |decode_time, composition_offset| Mp4Sample {
+            offset: 0,
+            size: 1,
+            decode_time,
+            composition_offset,
+            duration: 40,
+            is_keyframe: true,
+        };
+        let video = Mp4Track {
+            track_id: 1,
+            kind: Mp4TrackKind::Video {
+                width: 640,
+                height: 360,
+            },
+            codec: Mp4Codec::Avc(vec![]),
+            timescale: 1000,
+            // B-frames delay the presentation by one frame.
+            media_time: 40,
+            samples: vec![sample(0, 40), sample(40, 80), sample(80, 0)],
+        };
+        let audio = Mp4Track {
+            kind: Mp4TrackKind::Audio {
+                sample_rate: 48000.,
+                channels: 2,
+            },
+            codec: Mp4Codec::Aac(vec![]),
+            timescale: 48000,
+            media_time: 0,
+            samples: vec![sample(0, 0), sample(1024, 0)],
+            ..video.clone()
+        };
+
+        let times = |blocks: &[PendingBlock], track_number| {
+            blocks
+                .iter()
+                .filter(|b| b.track_number == track_number)
+                .map(|b| (b.decode_time, b.presentation_time))
+                .collect::<Vec<_>>()
+        };
+
+        let blocks = get_pending_blocks(&video, &audio, &[]);
+        assert_eq!(
+            times(&blocks, VIDEO_TRACK_NUMBER),
+            vec![(-40, 0), (0, 80), (40, 40)]
+        );
+        assert_eq!(times(&blocks, AUDIO_TRACK_NUMBER), vec![(0, 0), (21, 21)]);
+
+        // Without the edit list, nothing may be presented before zero.
+        let video = Mp4Track {
+            media_time: 0,
+            samples: vec![sample(0, -20), sample(40, 0)],
+            ..video
+        };
+        let blocks = get_pending_blocks(&video, &audio, &[]);
+        assert_eq!(times(&blocks, VIDEO_TRACK_NUMBER), vec![(20, 0), (60, 60)]);
+        assert_eq!(times(&blocks, AUDIO_TRACK_NUMBER), vec![(20, 20), (41, 41)]);
+    }
+
+    #[test]
+    fn write_matroska_round_trips_a_fragmented_mp4() {
+        let video_samples = vec![
+            vec![
+                TestSample::new(40, true, b"video 0"),
+                TestSample::new(40, false, b"video 1"),
+            ],
+            vec![TestSample::new(40, true, b"video 2")],
+        ];
+        let audio_samples = vec![vec![
+            TestSample::new(30, true, b"audio 0"),
+            TestSample::new(30, true, b"audio 1"),
+            TestSample::new(30, true, b"audio 2"),
+            TestSample::new(30, true, b"audio 3"),
+        ]];
+
+        let video_path = get_temporary_path("matroska_video.m4s");
+        let audio_path = get_temporary_path("matroska_audio.m4s");
+        let subtitle_path = get_temporary_path("matroska_subtitle.ass");
+        let output_path = get_temporary_path("matroska_episode.mkv");
+        fs::write(&video_path, fragmented_mp4(true, None, &video_samples)).unwrap();
+        fs::write(&audio_path, fragmented_mp4(false, None, &audio_samples)).unwrap();
+        fs::write(&subtitle_path, ASS).unwrap();
+
+        let subtitle_track = SubtitleTrack {
+            path: subtitle_path.clone(),
+            language: "en".into(),
+            title: None,
+            keep_fonts: false,
+        };
+        let result = write_matroska(&MuxJob {
+            video_path: video_path.clone(),
+            audio_path: audio_path.clone(),
+            subtitle_tracks: vec![subtitle_track.clone()],
+            default_track: 0,
+            subtitle_type: SubtitleType::Soft,
+            title: "EP01 - Pilot".into(),
+            output_path: output_path.clone(),
+            duration: None,
+            attachments: vec![],
+        });
+        let output = fs::read(&output_path);
+        [video_path, audio_path, subtitle_path, output_path]
+            .iter()
+            .for_each(|p| {
+                let _ = fs::remove_file(p);
+            });
+        result.unwrap();
+        let output = output.unwrap();
+
+        let elements = read_elements(&output);
+        assert_eq!(
+            elements.iter().map(|e| e.id).collect::<Vec<_>>(),
+            vec![EBML, SEGMENT]
+        );
+        assert_eq!(elements[0].find(DOC_TYPE).string(), "matroska");
+
+        let segment = &elements[1];
+        assert_eq!(segment.find(INFO).find(TITLE).string(), "EP01 - Pilot");
+
+        // Seek positions point to the elements they name, relative to the segment data.
+        let segment_start = output.len() - segment.data.len();
+        for seek in segment.find(SEEK_HEAD).find_all(SEEK) {
+            let position = segment_start + seek.find(SEEK_POSITION).uint() as usize;
+
+            assert!(output[position..].starts_with(&seek.find(SEEK_ID).data));
+        }
+
+        let tracks: Vec<_> = segment.find(TRACKS).find_all(TRACK_ENTRY).collect();
+        assert_eq!(
+            tracks
+                .iter()
+                .map(|t| (t.find(TRACK_NUMBER).uint(), t.find(CODEC_ID).string()))
+                .collect::<Vec<_>>(),
+            vec![(1, "V_MPEG4/ISO/AVC"), (2, "A_AAC"), (3, "S_TEXT/ASS")]
+        );
+        assert_eq!(tracks[0].find(CODEC_PRIVATE).data, AVC_CONFIG);
+        assert_eq!(tracks[0].find(VIDEO).find(PIXEL_WIDTH).uint(), 640);
+        assert_eq!(tracks[1].find(CODEC_PRIVATE).data, AAC_CONFIG);
+        assert_eq!(tracks[1].find(AUDIO).find(CHANNELS).uint(), 2);
+        assert_eq!(tracks[2].find(NAME).string(), subtitle_track.get_title());
+        assert_eq!(tracks[2].find(LANGUAGE).string(), "eng");
+        assert_eq!(tracks[2].find(FLAG_DEFAULT).uint(), 1);
+        assert!(tracks[2]
+            .find(CODEC_PRIVATE)
+            .string()
+            .starts_with("[Script Info]"));
+
+        // Track number, absolute time, keyframe flag, and data of every block.
+        let mut blocks = vec![];
+        for cluster in segment.find_all(CLUSTER) {
+            let cluster_time = cluster.find(TIMESTAMP).uint() as i64;
+
+            for e in &cluster.children {
+                let block = match e.id {
+                    SIMPLE_BLOCK => &e.data,
+                    BLOCK_GROUP => &e.find(BLOCK).data,
+                    _ => continue,
+                };
+                let relative_time = i16::from_be_bytes([block[1], block[2]]) as i64;
+
+                blocks.push((
+                    block[0] & 0x7F,
+                    cluster_time + relative_time,
+                    block[3] & 0x80 != 0,
+                    block[4..].to_vec(),
+                ));
+            }
+        }
+
+        let get_track = |track_number| {
+            blocks
+                .iter()
+                .filter(|b| b.0 == track_number)
+                .map(|b| (b.1, b.2, String::from_utf8(b.3.clone()).unwrap()))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            get_track(1),
+            vec![
+                (0, true, "video 0".into()),
+                (40, false, "video 1".into()),
+                (80, true, "video 2".into()),
+            ]
+        );
+        assert_eq!(
+            get_track(2),
+            (0..4)
+                .map(|i| (i * 30, true, format!("audio {i}")))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            get_track(3),
+            vec![
+                (50, false, "0,0,Default,,0,0,0,,Hello, world".into()),
+                (
+                    62_500,
+                    false,
+                    "1,1,Top,Speaker,10,20,30,Banner,{\\an8}Second".into()
+                ),
+            ]
+        );
+
+        // Every keyframe starts a cluster, which is listed in the cues.
+        assert_eq!(
+            segment
+                .find(CUES)
+                .find_all(CUE_POINT)
+                .map(|c| c.find(CUE_TIME).uint())
+                .collect::<Vec<_>>(),
+            vec![0, 80]
+        );
+    }
+}
@@ -1,30 +1,345 @@
-use crate::error::Result;
-use serde::{Deserialize, Serialize};
+use crate::{
+    combiner::get_m4s_padding_length,
+    error::{Error, Result},
+    mp4::Mp4Track,
+    subtitle::SubtitleFormat,
+};
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::{Map, Value};
 use std::{
+    collections::BTreeMap,
     fmt::Display,
     fs::read_to_string,
     path::{Path, PathBuf},
+    time::Duration,
 };
 
 /// Contains information inside a Bilibili JSON Entry file.
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+///
+/// Fields that are not modeled here are kept inside `extra`, so nothing is lost when the entry is
+/// serialized back.
+///
+/// # Parse an entry
+///
+/// ```
+/// use bilibili_extractor_lib::metadata::JsonEntry;
+///
+/// let json_entry: JsonEntry = serde_json::from_str(
+///     r#"{
+///         "title": "Season",
+///         "type_tag": "64",
+///         "season_id": "12345",
+///         "is_completed": true,
+///         "total_bytes": 1024,
+///         "downloaded_bytes": 1024,
+///         "ep": { "index": "1", "index_title": "The Storm", "episode_id": 42 },
+///         "unknown_field": [1, 2, 3]
+///     }"#,
+/// )
+/// .unwrap();
+///
+/// assert_eq!(json_entry.season_id.as_deref(), Some("12345"));
+/// assert_eq!(json_entry.ep.unwrap().episode_id, Some(42));
+/// assert!(json_entry.extra.contains_key("unknown_field"));
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub struct JsonEntry {
     pub title: String,
-    pub ep: JsonEntryEpisodeMetadata,
+    /// Only present on bangumi downloads.
+    #[serde(default)]
+    pub ep: Option<JsonEntryEpisodeMetadata>,
     pub type_tag: String,
+    #[serde(default)]
+    pub media_type: Option<u32>,
+    #[serde(default)]
+    pub has_dash_audio: Option<bool>,
+    #[serde(default)]
+    pub is_completed: Option<bool>,
+    #[serde(default)]
+    pub total_bytes: Option<u64>,
+    #[serde(default)]
+    pub downloaded_bytes: Option<u64>,
+    #[serde(default)]
+    pub guessed_total_bytes: Option<u64>,
+    #[serde(default)]
+    pub total_time_milli: Option<u64>,
+    #[serde(default)]
+    pub danmaku_count: Option<u64>,
+    #[serde(default)]
+    pub cover: Option<String>,
+    #[serde(default)]
+    pub video_quality: Option<u32>,
+    #[serde(default)]
+    pub prefered_video_quality: Option<u32>,
+    #[serde(default)]
+    pub quality_pithy_description: Option<String>,
+    #[serde(default)]
+    pub quality_superscript: Option<String>,
+    #[serde(default)]
+    pub audio_quality: Option<u32>,
+    #[serde(default)]
+    pub preferred_audio_quality: Option<u32>,
+    #[serde(default)]
+    pub time_create_stamp: Option<u64>,
+    #[serde(default)]
+    pub time_update_stamp: Option<u64>,
+    #[serde(default)]
+    pub can_play_in_advance: Option<bool>,
+    #[serde(default)]
+    pub interrupt_transform_temp_file: Option<bool>,
+    #[serde(default)]
+    pub cache_version_code: Option<u64>,
+    #[serde(default)]
+    pub avid: Option<u64>,
+    #[serde(default)]
+    pub bvid: Option<String>,
+    #[serde(default)]
+    pub spid: Option<u64>,
+    /// Bangumi entries store it as a string, while uploader videos use a number.
+    #[serde(default, deserialize_with = "deserialize_optional_id")]
+    pub season_id: Option<String>,
+    /// Misspelled by Bilibili. Only present on uploader videos.
+    #[serde(default, deserialize_with = "deserialize_optional_id")]
+    pub seasion_id: Option<String>,
+    #[serde(default)]
+    pub owner_id: Option<u64>,
+    #[serde(default)]
+    pub owner_name: Option<String>,
+    #[serde(default)]
+    pub owner_avatar: Option<String>,
+    #[serde(default)]
+    pub source: Option<JsonEntrySource>,
+    /// Only present on uploader video downloads.
+    #[serde(default)]
+    pub page_data: Option<JsonEntryPageData>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
 }
 
 /// Contains information about the episode. It can be found inside a Bilibili JSON file.
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub struct JsonEntryEpisodeMetadata {
+    #[serde(default)]
     pub index_title: String,
     pub index: String,
+    #[serde(default)]
+    pub av_id: Option<u64>,
+    #[serde(default)]
+    pub page: Option<u32>,
+    #[serde(default)]
+    pub danmaku: Option<u64>,
+    #[serde(default)]
+    pub cover: Option<String>,
+    #[serde(default)]
+    pub episode_id: Option<u64>,
+    #[serde(default)]
+    pub from: Option<String>,
+    #[serde(default)]
+    pub season_type: Option<u32>,
+    #[serde(default)]
+    pub width: Option<u32>,
+    #[serde(default)]
+    pub height: Option<u32>,
+    #[serde(default)]
+    pub rotate: Option<u32>,
+    #[serde(default)]
+    pub link: Option<String>,
+    #[serde(default)]
+    pub bvid: Option<String>,
+    #[serde(default)]
+    pub sort_index: Option<u64>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+/// Contains information about a part of an uploader video. It can be found inside a Bilibili JSON
+/// file.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct JsonEntryPageData {
+    pub cid: u64,
+    pub page: u32,
+    #[serde(default)]
+    pub part: String,
+    #[serde(default)]
+    pub from: Option<String>,
+    #[serde(default)]
+    pub link: Option<String>,
+    #[serde(default)]
+    pub vid: Option<String>,
+    #[serde(default)]
+    pub has_alias: Option<bool>,
+    #[serde(default)]
+    pub tid: Option<u64>,
+    #[serde(default)]
+    pub width: Option<u32>,
+    #[serde(default)]
+    pub height: Option<u32>,
+    #[serde(default)]
+    pub rotate: Option<u32>,
+    #[serde(default)]
+    pub download_title: Option<String>,
+    #[serde(default)]
+    pub download_subtitle: Option<String>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+/// Contains information about where the episode was downloaded from. It can be found inside a
+/// Bilibili JSON file.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct JsonEntrySource {
+    #[serde(default)]
+    pub av_id: Option<u64>,
+    #[serde(default)]
+    pub cid: Option<u64>,
+    #[serde(default)]
+    pub website: Option<String>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+/// Contains information inside the `.videoInfo` file of a video downloaded by the Bilibili PC
+/// client.
+///
+/// ```
+/// use bilibili_extractor_lib::metadata::JsonVideoInfo;
+///
+/// let video_info: JsonVideoInfo = serde_json::from_str(
+///     r#"{
+///         "type": "ugc",
+///         "groupTitle": "Uploader Video",
+///         "title": "Part Title",
+///         "p": 2,
+///         "cid": 123456,
+///         "bvid": "BV1xx411c7mD"
+///     }"#,
+/// )
+/// .unwrap();
+///
+/// assert_eq!(video_info.group_title, "Uploader Video");
+/// assert_eq!(video_info.cid.as_deref(), Some("123456"));
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct JsonVideoInfo {
+    /// Title of the season or uploader video.
+    pub group_title: String,
+    /// Title of the episode or part.
+    #[serde(default)]
+    pub title: String,
+    /// `pgc` for seasons, e.g. anime, and `ugc` for uploader videos.
+    #[serde(default, rename = "type")]
+    pub video_type: Option<String>,
+    /// Number of the episode or part.
+    #[serde(default)]
+    pub p: Option<u32>,
+    #[serde(default, deserialize_with = "deserialize_optional_id")]
+    pub aid: Option<String>,
+    #[serde(default, deserialize_with = "deserialize_optional_id")]
+    pub cid: Option<String>,
+    #[serde(default)]
+    pub bvid: Option<String>,
+    /// `completed` once the download finished.
+    #[serde(default)]
+    pub status: Option<String>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+/// Contains the `playurl` API response saved as `.playurl` by the Bilibili PC client.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct JsonPlayUrl {
+    #[serde(default)]
+    pub data: Option<JsonPlayUrlData>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct JsonPlayUrlData {
+    /// Length of the video in milliseconds.
+    #[serde(default)]
+    pub timelength: Option<u64>,
+    #[serde(default)]
+    pub dash: Option<JsonPlayUrlDash>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct JsonPlayUrlDash {
+    #[serde(default)]
+    pub video: Vec<JsonPlayUrlStream>,
+    #[serde(default)]
+    pub audio: Vec<JsonPlayUrlStream>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+/// A stream of a `playurl` API response. The downloaded `.m4s` file of an audio stream ends with
+/// its `id`, e.g. `123456-1-30280.m4s`.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct JsonPlayUrlStream {
+    pub id: u32,
+    #[serde(default)]
+    pub width: Option<u32>,
+    #[serde(default)]
+    pub height: Option<u32>,
+    #[serde(default)]
+    pub codecs: Option<String>,
+    /// Size of the stream in bytes, without the padding of the downloaded file.
+    #[serde(default)]
+    pub size: Option<u64>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+/// Contains the segments of an older download, found inside the `index.json` of its media
+/// directory.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct JsonIndex {
+    #[serde(default)]
+    pub segment_list: Vec<JsonIndexSegment>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+/// A segment of an older download. Segments are stored as `0.blv`, `1.blv`, and so on.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct JsonIndexSegment {
+    /// Length of the segment in milliseconds.
+    #[serde(default)]
+    pub duration: Option<u64>,
+    #[serde(default)]
+    pub bytes: Option<u64>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+/// How the audio and video of an episode are stored inside its media (`type_tag`) directory.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+pub enum MediaLayout {
+    /// Separate `video.m4s` and `audio.m4s` files.
+    Dash {
+        video_path: PathBuf,
+        audio_path: PathBuf,
+        /// Length of the padding before the `ftyp` box of the video, see
+        /// [`get_m4s_padding_length`].
+        video_padding: u64,
+        /// Length of the padding before the `ftyp` box of the audio.
+        audio_padding: u64,
+    },
+    /// FLV segments containing both audio and video, in order. Used by older clients.
+    Segmented(Vec<PathBuf>),
 }
 
 /// The download folder of Bilibili. Contains all the seasons downloaded.
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
 pub struct DownloadFolder {
     pub seasons: Vec<SeasonMetadata>,
+    /// Videos of the PC client that couldn't be read. Other episodes are kept by their season.
+    #[serde(default)]
+    pub invalid_episodes: Vec<InvalidEpisode>,
 }
 
 /// Contains information of the entire season, including its episode, both normal and special.
@@ -33,12 +348,47 @@ pub struct SeasonMetadata {
     pub title: String,
     pub path: PathBuf,
     pub episodes: Vec<EpisodeMetadata>,
+    /// Episodes that couldn't be read, which are left out instead of failing the season.
+    #[serde(default)]
+    pub invalid_episodes: Vec<InvalidEpisode>,
+}
+
+/// An episode directory that couldn't be read, e.g. because its `entry.json` is malformed.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+pub struct InvalidEpisode {
+    pub path: PathBuf,
+    /// The error that occurred while reading the episode.
+    pub reason: String,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
 pub enum EpisodeId {
     Normal(usize),
     Special(String),
+    /// A part of a multi-part uploader video.
+    Part(usize),
+}
+
+/// Whether the download of an episode finished, as recorded by the download.
+///
+/// ```
+/// use bilibili_extractor_lib::metadata::DownloadStatus;
+///
+/// let status = DownloadStatus::new(None, Some(512), Some(1024));
+///
+/// assert!(status.is_incomplete());
+/// assert_eq!(status.get_percentage(), Some(50.0));
+/// ```
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+pub enum DownloadStatus {
+    Completed,
+    Incomplete {
+        downloaded_bytes: Option<u64>,
+        total_bytes: Option<u64>,
+    },
+    /// The download doesn't record whether it finished.
+    #[default]
+    Unknown,
 }
 
 /// Contains information of the normal episode.
@@ -46,65 +396,188 @@ pub enum EpisodeId {
 pub struct EpisodeMetadata {
     pub title: String,
     pub episode: EpisodeId,
+    /// Title of the episode itself (`index_title`), or the part title for uploader videos.
+    pub episode_title: Option<String>,
     pub path: PathBuf,
     pub type_tag: String,
+    /// Length of the episode, if the download records it.
+    pub duration: Option<Duration>,
+    /// Width and height of the video, if the download records it.
+    pub resolution: Option<(u32, u32)>,
+    #[serde(default)]
+    pub download_status: DownloadStatus,
 }
 
 impl DownloadFolder {
     /// Creates a `DownloadFolder` from path.
     pub fn new_from_path(path: impl AsRef<Path>) -> Result<Self> {
         let mut seasons = vec![];
+        let read_dir = path.as_ref().read_dir().map_err(|e| {
+            Error::InputError(format!("Cannot read {}: {e}", path.as_ref().display()))
+        })?;
+
+        // Videos of the PC client aren't inside season directories, so they're grouped by title.
+        let mut pc_seasons: BTreeMap<String, Vec<EpisodeMetadata>> = BTreeMap::new();
+        let mut invalid_episodes = vec![];
+
+        for p in read_dir {
+            let p = p?.path();
 
-        for p in path.as_ref().read_dir()? {
-            let season_metadata = match SeasonMetadata::new_from_path(p?.path()) {
-                Ok(v) => v,
-                Err(_) => continue,
-            };
+            if p.join(".videoInfo").is_file() {
+                match EpisodeMetadata::new_from_path(&p) {
+                    Ok(e) => pc_seasons.entry(e.title.clone()).or_default().push(e),
+                    Err(e) => invalid_episodes.push(InvalidEpisode::new(p, &e)),
+                }
+
+                continue;
+            }
+
+            // Files and directories without any episode aren't seasons.
+            if let Ok(season_metadata) = SeasonMetadata::new_from_path(p) {
+                seasons.push(season_metadata);
+            }
+        }
+
+        for (title, mut episodes) in pc_seasons {
+            episodes.sort();
+
+            seasons.push(SeasonMetadata {
+                title,
+                path: path.as_ref().into(),
+                episodes,
+                invalid_episodes: vec![],
+            });
+        }
 
-            seasons.push(season_metadata);
+        if seasons.is_empty() && invalid_episodes.is_empty() {
+            return Err(Error::InputError(format!(
+                "No seasons found in {}",
+                path.as_ref().display()
+            )));
         }
 
         seasons.sort();
+        invalid_episodes.sort();
 
-        Ok(Self { seasons })
+        Ok(Self {
+            seasons,
+            invalid_episodes,
+        })
+    }
+
+    /// Get every episode that couldn't be read, of every season.
+    pub fn get_invalid_episodes(&self) -> impl Iterator<Item = &InvalidEpisode> {
+        self.seasons
+            .iter()
+            .flat_map(|s| &s.invalid_episodes)
+            .chain(&self.invalid_episodes)
     }
 }
 
 impl SeasonMetadata {
-    /// Creates a `SeasonMetadata` from path.
+    /// Creates a `SeasonMetadata` from path. Episodes that can't be read are kept in
+    /// `invalid_episodes`, and the title is the name of the directory if none can be read.
     pub fn new_from_path(path: impl AsRef<Path>) -> Result<Self> {
-        let json_entry = serde_json::from_str::<JsonEntry>(&read_to_string(
-            path.as_ref()
-                .read_dir()?
-                .next()
-                .ok_or(format!("No episodes found in {}", path.as_ref().display()))??
-                .path()
-                .join("entry.json"),
-        )?)?;
-
-        let mut season_metadata = Self {
-            title: json_entry.title,
-            path: path.as_ref().into(),
-            episodes: vec![],
-        };
+        let path = path.as_ref();
+        let mut episodes = vec![];
+        let mut invalid_episodes = vec![];
+
+        for p in path.read_dir()? {
+            let p = p?.path();
 
-        for p in path.as_ref().read_dir()? {
-            let episode_metadata = EpisodeMetadata::new_from_path(p?.path())?;
+            if !p.is_dir() {
+                continue;
+            }
 
-            season_metadata.episodes.push(episode_metadata);
+            match EpisodeMetadata::new_from_path(&p) {
+                Ok(e) => episodes.push(e),
+                Err(e) => invalid_episodes.push(InvalidEpisode::new(p, &e)),
+            }
         }
 
-        season_metadata.episodes.sort();
+        episodes.sort();
+        invalid_episodes.sort();
 
-        Ok(season_metadata)
+        let title = match (episodes.first(), path.file_name()) {
+            (Some(e), _) => e.title.clone(),
+            (None, Some(n)) if !invalid_episodes.is_empty() => n.to_string_lossy().into_owned(),
+            _ => return Err(format!("No episodes found in {}", path.display()).into()),
+        };
+
+        Ok(Self {
+            title,
+            path: path.into(),
+            episodes,
+            invalid_episodes,
+        })
+    }
+}
+
+impl InvalidEpisode {
+    pub fn new(path: PathBuf, error: &Error) -> Self {
+        Self {
+            path,
+            reason: error.to_string(),
+        }
     }
 }
 
 impl EpisodeId {
+    /// Get a short display of the episode, e.g. `EP01`, `P01`, or the special index itself.
+    pub fn get_short_display(&self) -> String {
+        match self {
+            EpisodeId::Normal(v) => format!("EP{:0>2}", v),
+            EpisodeId::Special(v) => v.to_string(),
+            EpisodeId::Part(v) => format!("P{:0>2}", v),
+        }
+    }
+
     pub fn get_full_display(&self) -> String {
         match self {
             EpisodeId::Normal(v) => format!("Episode {:0>2}", v),
             EpisodeId::Special(v) => v.to_string(),
+            EpisodeId::Part(v) => format!("Part {:0>2}", v),
+        }
+    }
+}
+
+impl DownloadStatus {
+    /// Create a `DownloadStatus` from the `is_completed`, `downloaded_bytes` and `total_bytes` of
+    /// `entry.json`. The bytes are only compared if `is_completed` is missing.
+    pub fn new(
+        is_completed: Option<bool>,
+        downloaded_bytes: Option<u64>,
+        total_bytes: Option<u64>,
+    ) -> Self {
+        let incomplete = DownloadStatus::Incomplete {
+            downloaded_bytes,
+            total_bytes,
+        };
+
+        match (is_completed, downloaded_bytes, total_bytes) {
+            (Some(true), _, _) => DownloadStatus::Completed,
+            (Some(false), _, _) => incomplete,
+            (None, Some(d), Some(t)) if t > 0 => match d >= t {
+                true => DownloadStatus::Completed,
+                false => incomplete,
+            },
+            _ => DownloadStatus::Unknown,
+        }
+    }
+
+    /// Returns `true` if the download is known to be unfinished.
+    pub fn is_incomplete(&self) -> bool {
+        matches!(self, DownloadStatus::Incomplete { .. })
+    }
+
+    /// Get the percentage of downloaded bytes of an unfinished download, if they're recorded.
+    pub fn get_percentage(&self) -> Option<f64> {
+        match self {
+            DownloadStatus::Incomplete {
+                downloaded_bytes: Some(d),
+                total_bytes: Some(t),
+            } if *t > 0 => Some(*d as f64 / *t as f64 * 100.0),
+            _ => None,
         }
     }
 }
@@ -114,27 +587,192 @@ impl Display for EpisodeId {
         match self {
             EpisodeId::Normal(v) => write!(f, "{:0>2}", v),
             EpisodeId::Special(v) => write!(f, "{}", v),
+            EpisodeId::Part(v) => write!(f, "{:0>2}", v),
         }
     }
 }
 
 impl EpisodeMetadata {
-    /// Create an episode metadata from path.
+    /// Create an episode metadata from path. The `.videoInfo` of the PC client is read if there
+    /// is no `entry.json`.
     pub fn new_from_path(path: impl AsRef<Path>) -> Result<Self> {
-        let json =
-            serde_json::from_str::<JsonEntry>(&read_to_string(path.as_ref().join("entry.json"))?)?;
+        let path = path.as_ref();
+        let entry_path = path.join("entry.json");
+        let video_info_path = path.join(".videoInfo");
+
+        if entry_path.is_file() || !video_info_path.is_file() {
+            let json = JsonEntry::new_from_path(entry_path)?;
+
+            return Ok(Self::try_from(json)?.set_path(path.into()));
+        }
+
+        let mut episode_metadata =
+            Self::try_from(JsonVideoInfo::new_from_path(video_info_path)?)?.set_path(path.into());
+
+        // The length and resolution are only recorded in `.playurl`.
+        if let Some(data) = JsonPlayUrl::new_from_path(path.join(".playurl"))
+            .ok()
+            .and_then(|p| p.data)
+        {
+            // Compare the downloaded files with the sizes of their streams, since the status
+            // isn't always recorded.
+            let download_bytes = data.dash.as_ref().and_then(|d| {
+                let layout = get_pc_media_layout(path, Some(d)).ok().flatten()?;
 
-        Ok(Self::from(json).set_path(path.as_ref().into()))
+                get_pc_download_bytes(&layout, d)
+            });
+            episode_metadata.download_status =
+                match (episode_metadata.download_status, download_bytes) {
+                    (DownloadStatus::Unknown, Some((d, t))) => {
+                        DownloadStatus::new(None, Some(d), Some(t))
+                    }
+                    // A download recorded as completed whose files are short was interrupted.
+                    (DownloadStatus::Completed, Some((d, t))) if d < t => {
+                        DownloadStatus::Incomplete {
+                            downloaded_bytes: Some(d),
+                            total_bytes: Some(t),
+                        }
+                    }
+                    (DownloadStatus::Incomplete { .. }, Some((d, t))) => {
+                        DownloadStatus::Incomplete {
+                            downloaded_bytes: Some(d),
+                            total_bytes: Some(t),
+                        }
+                    }
+                    (s, _) => s,
+                };
+
+            episode_metadata.duration = data.timelength.map(Duration::from_millis);
+            episode_metadata.resolution = data
+                .dash
+                .and_then(|d| d.video.into_iter().next())
+                .and_then(|v| v.width.zip(v.height))
+                .filter(|(w, h)| *w > 0 && *h > 0);
+        }
+
+        Ok(episode_metadata)
     }
 
+    /// Get the path of the subtitle file inside the language directory. Files that aren't
+    /// subtitles are ignored.
     pub fn get_subtitle_path(&self, subtitle_language: &str) -> Result<PathBuf> {
-        Ok(self
-            .path
-            .join(subtitle_language)
-            .read_dir()?
-            .next()
-            .ok_or("Subtitle directory is empty.")??
-            .path())
+        get_subtitle_file(&self.path.join(subtitle_language))?.ok_or(
+            format!(
+                "No subtitle found in {}.",
+                self.path.join(subtitle_language).display()
+            )
+            .into(),
+        )
+    }
+
+    /// Get the directory containing the audio and video of the episode.
+    pub fn get_media_path(&self) -> PathBuf {
+        self.path.join(&self.type_tag)
+    }
+
+    /// Find out how the audio and video of the episode are stored. Segments are listed by
+    /// `index.json`, or found by their numbered file names if it's missing.
+    pub fn get_media_layout(&self) -> Result<MediaLayout> {
+        let media_path = self.get_media_path();
+        let video_path = media_path.join("video.m4s");
+
+        if video_path.is_file() {
+            // The Android client doesn't pad its files.
+            return Ok(MediaLayout::Dash {
+                video_path,
+                audio_path: media_path.join("audio.m4s"),
+                video_padding: 0,
+                audio_padding: 0,
+            });
+        }
+
+        let dash = JsonPlayUrl::new_from_path(media_path.join(".playurl"))
+            .ok()
+            .and_then(|p| p.data?.dash);
+
+        if let Some(layout) = get_pc_media_layout(&media_path, dash.as_ref())? {
+            return Ok(layout);
+        }
+
+        let index_path = media_path.join("index.json");
+        let segments = match index_path.is_file() {
+            true => (0..JsonIndex::new_from_path(&index_path)?.segment_list.len())
+                .map(|i| {
+                    get_segment_file(&media_path, i).ok_or_else(|| {
+                        Error::InputError(format!(
+                            "Segment {i} listed in {} is missing.",
+                            index_path.display()
+                        ))
+                    })
+                })
+                .collect::<Result<Vec<_>>>()?,
+            false => (0..)
+                .map_while(|i| get_segment_file(&media_path, i))
+                .collect(),
+        };
+
+        if segments.is_empty() {
+            return Err(Error::InputError(format!(
+                "No video found in {}",
+                media_path.display()
+            )));
+        }
+
+        Ok(MediaLayout::Segmented(segments))
+    }
+
+    /// Get the path of the `danmaku.xml` of the episode, if it exists.
+    pub fn get_danmaku_path(&self) -> Option<PathBuf> {
+        Some(self.path.join("danmaku.xml")).filter(|p| p.is_file())
+    }
+
+    /// Get the subtitle languages available for the episode. A subtitle language is a directory
+    /// inside the episode that contains a subtitle file. The `type_tag` media directory is never
+    /// a subtitle language.
+    pub fn get_subtitle_languages(&self) -> Result<Vec<String>> {
+        let mut subtitle_languages = vec![];
+
+        for p in self.path.read_dir()? {
+            let p = p?;
+
+            if !p.file_type()?.is_dir()
+                || p.file_name() == self.type_tag.as_str()
+                || get_subtitle_file(&p.path())?.is_none()
+            {
+                continue;
+            }
+
+            subtitle_languages.push(p.file_name().to_string_lossy().into_owned());
+        }
+
+        subtitle_languages.sort();
+
+        Ok(subtitle_languages)
+    }
+
+    /// Get the display of the episode including its title, e.g. `EP03 - The Storm`.
+    pub fn get_episode_display(&self) -> String {
+        match &self.episode_title {
+            Some(t) => format!("{} - {t}", self.episode.get_short_display()),
+            None => self.episode.get_short_display(),
+        }
+    }
+
+    /// Get the width and height of the video. The video of DASH layouts is read first, since
+    /// `entry.json` may record a different quality than the one downloaded.
+    pub fn get_video_resolution(&self) -> Option<(u32, u32)> {
+        let resolution = match self.get_media_layout() {
+            Ok(MediaLayout::Dash {
+                video_path,
+                video_padding,
+                ..
+            }) => Mp4Track::read_video_resolution(video_path, video_padding)
+                .ok()
+                .flatten(),
+            _ => None,
+        };
+
+        resolution.or(self.resolution)
     }
 
     pub fn set_path(mut self, path: PathBuf) -> Self {
@@ -144,21 +782,500 @@ impl EpisodeMetadata {
     }
 }
 
-impl From<JsonEntry> for EpisodeMetadata {
-    fn from(val: JsonEntry) -> Self {
-        match val.ep.index.parse::<usize>() {
-            Ok(e) => EpisodeMetadata {
-                title: val.title,
-                episode: EpisodeId::Normal(e),
-                path: Default::default(),
-                type_tag: val.type_tag,
+impl JsonEntry {
+    /// Create a `JsonEntry` from the path of an `entry.json` file.
+    pub fn new_from_path(path: impl AsRef<Path>) -> Result<Self> {
+        Ok(serde_json::from_str(&read_to_string(path)?)?)
+    }
+}
+
+impl JsonVideoInfo {
+    /// Create a `JsonVideoInfo` from the path of a `.videoInfo` file.
+    pub fn new_from_path(path: impl AsRef<Path>) -> Result<Self> {
+        Ok(serde_json::from_str(&read_to_string(path)?)?)
+    }
+}
+
+impl JsonPlayUrl {
+    /// Create a `JsonPlayUrl` from the path of a `.playurl` file.
+    pub fn new_from_path(path: impl AsRef<Path>) -> Result<Self> {
+        Ok(serde_json::from_str(&read_to_string(path)?)?)
+    }
+}
+
+impl JsonIndex {
+    /// Create a `JsonIndex` from the path of an `index.json` file.
+    pub fn new_from_path(path: impl AsRef<Path>) -> Result<Self> {
+        Ok(serde_json::from_str(&read_to_string(path)?)?)
+    }
+}
+
+/// Find the audio and video downloaded by the PC client, e.g. `123456-1-30080.m4s` and
+/// `123456-1-30280.m4s`. Streams listed as audio by `.playurl`, or with an id from `30200` to
+/// `30299`, are audio. Returns `None` if there are no such files.
+///
+/// Returns `Error::InputError` if the media directory can't be read.
+fn get_pc_media_layout(
+    media_path: &Path,
+    dash: Option<&JsonPlayUrlDash>,
+) -> Result<Option<MediaLayout>> {
+    let audio_ids: Vec<u32> = dash
+        .map(|d| d.audio.iter().map(|a| a.id).collect())
+        .unwrap_or_default();
+
+    let mut streams = vec![];
+
+    let entries = media_path
+        .read_dir()
+        .map_err(|e| Error::InputError(format!("Cannot read {}: {e}", media_path.display())))?;
+
+    for p in entries {
+        let p = p?.path();
+
+        if let Some(id) = get_pc_stream_id(&p) {
+            streams.push((audio_ids.contains(&id) || (30200..30300).contains(&id), p));
+        }
+    }
+
+    if streams.is_empty() {
+        return Ok(None);
+    }
+
+    streams.sort();
+
+    let video_path = streams.iter().find(|(a, _)| !a).map(|(_, p)| p.clone());
+    let audio_path = streams.iter().find(|(a, _)| *a).map(|(_, p)| p.clone());
+
+    match (video_path, audio_path) {
+        (Some(video_path), Some(audio_path)) => Ok(Some(MediaLayout::Dash {
+            video_padding: get_m4s_padding_length(&video_path)?,
+            audio_padding: get_m4s_padding_length(&audio_path)?,
+            video_path,
+            audio_path,
+        })),
+        _ => Err(Error::InputError(format!(
+            "Cannot find both the audio and video in {}",
+            media_path.display()
+        ))),
+    }
+}
+
+/// Get the stream id of an `.m4s` file of the PC client, e.g. `30280` for `123456-1-30280.m4s`.
+fn get_pc_stream_id(path: &Path) -> Option<u32> {
+    path.file_stem()
+        .filter(|_| path.extension().is_some_and(|e| e == "m4s"))
+        .and_then(|s| s.to_str()?.rsplit('-').next()?.parse().ok())
+}
+
+/// Get the downloaded and total bytes of the audio and video downloaded by the PC client, if
+/// `.playurl` records the size of both. Video files are named after `30000` plus the id of their
+/// stream.
+fn get_pc_download_bytes(layout: &MediaLayout, dash: &JsonPlayUrlDash) -> Option<(u64, u64)> {
+    let MediaLayout::Dash {
+        video_path,
+        audio_path,
+        video_padding,
+        audio_padding,
+    } = layout
+    else {
+        return None;
+    };
+
+    let get_bytes = |path: &Path, padding: u64, streams: &[JsonPlayUrlStream]| {
+        let id = get_pc_stream_id(path)?;
+        let size = streams
+            .iter()
+            .find(|s| s.id == id || s.id.checked_add(30000) == Some(id))?
+            .size?;
+
+        Some((path.metadata().ok()?.len().saturating_sub(padding), size))
+    };
+
+    let (video_bytes, video_size) = get_bytes(video_path, *video_padding, &dash.video)?;
+    let (audio_bytes, audio_size) = get_bytes(audio_path, *audio_padding, &dash.audio)?;
+
+    Some((video_bytes + audio_bytes, video_size + audio_size))
+}
+
+/// Get the file of a numbered segment, e.g. `0.blv`, if it exists.
+fn get_segment_file(media_path: &Path, index: usize) -> Option<PathBuf> {
+    ["blv", "flv"]
+        .iter()
+        .map(|e| media_path.join(format!("{index}.{e}")))
+        .find(|p| p.is_file())
+}
+
+/// Get the first subtitle file inside a directory, if any.
+fn get_subtitle_file(directory: &Path) -> Result<Option<PathBuf>> {
+    for p in directory.read_dir()? {
+        let p = p?.path();
+
+        if p.is_file() && SubtitleFormat::new_from_path(&p).is_ok() {
+            return Ok(Some(p));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Deserialize an id that can either be a string or a number.
+fn deserialize_optional_id<'de, D>(deserializer: D) -> std::result::Result<Option<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match Option::<Value>::deserialize(deserializer)? {
+        None | Some(Value::Null) => Ok(None),
+        Some(Value::String(s)) => Ok(Some(s)),
+        Some(Value::Number(n)) => Ok(Some(n.to_string())),
+        Some(v) => Err(serde::de::Error::custom(format!("invalid id: {v}"))),
+    }
+}
+
+impl TryFrom<JsonVideoInfo> for EpisodeMetadata {
+    type Error = Error;
+
+    fn try_from(val: JsonVideoInfo) -> Result<Self> {
+        let number = val.p.unwrap_or(1) as usize;
+        let episode = match val.video_type.as_deref() {
+            Some("pgc") => EpisodeId::Normal(number),
+            _ => EpisodeId::Part(number),
+        };
+
+        Ok(EpisodeMetadata {
+            title: val.group_title,
+            episode,
+            episode_title: Some(val.title).filter(|t| !t.is_empty()),
+            path: Default::default(),
+            // The PC client keeps the audio and video next to `.videoInfo`.
+            type_tag: String::new(),
+            duration: None,
+            resolution: None,
+            // Any other status is an unfinished download, e.g. one that's still downloading or
+            // paused.
+            download_status: match val.status.as_deref() {
+                Some("completed") => DownloadStatus::Completed,
+                Some(_) => DownloadStatus::Incomplete {
+                    downloaded_bytes: None,
+                    total_bytes: None,
+                },
+                None => DownloadStatus::Unknown,
             },
-            Err(_) => EpisodeMetadata {
-                title: val.title,
-                episode: EpisodeId::Special(val.ep.index),
-                path: Default::default(),
-                type_tag: val.type_tag,
+        })
+    }
+}
+
+impl TryFrom<JsonEntry> for EpisodeMetadata {
+    type Error = Error;
+
+    fn try_from(val: JsonEntry) -> Result<Self> {
+        let resolution = val
+            .ep
+            .as_ref()
+            .and_then(|e| e.width.zip(e.height))
+            .or_else(|| val.page_data.as_ref().and_then(|p| p.width.zip(p.height)))
+            .filter(|(w, h)| *w > 0 && *h > 0);
+
+        let (episode, episode_title) = match (val.ep, val.page_data) {
+            (Some(ep), _) => match ep.index.parse::<usize>() {
+                Ok(e) => (
+                    EpisodeId::Normal(e),
+                    Some(ep.index_title).filter(|t| !t.is_empty()),
+                ),
+                Err(_) => (
+                    EpisodeId::Special(ep.index),
+                    Some(ep.index_title).filter(|t| !t.is_empty()),
+                ),
             },
+            (None, Some(page_data)) => (
+                EpisodeId::Part(page_data.page as usize),
+                Some(page_data.part).filter(|p| !p.is_empty()),
+            ),
+            (None, None) => {
+                return Err(
+                    format!("Entry \"{}\" has neither `ep` nor `page_data`.", val.title).into(),
+                )
+            }
+        };
+
+        Ok(EpisodeMetadata {
+            title: val.title,
+            episode,
+            episode_title,
+            path: Default::default(),
+            type_tag: val.type_tag,
+            duration: val.total_time_milli.map(Duration::from_millis),
+            resolution,
+            download_status: DownloadStatus::new(
+                val.is_completed,
+                val.downloaded_bytes,
+                val.total_bytes,
+            ),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mp4::tests::{get_temporary_path, mp4_box};
+    use std::fs;
+
+    /// Write an `entry.json` of an episode, with `extra` fields added to it.
+    fn write_entry(episode_path: &Path, index: usize, extra: &str) {
+        fs::create_dir_all(episode_path).unwrap();
+        fs::write(
+            episode_path.join("entry.json"),
+            format!(
+                r#"{{"title": "Anime", "type_tag": "64", "ep": {{"index": "{index}"}}{extra}}}"#
+            ),
+        )
+        .unwrap();
+    }
+
+    /// Write an `.m4s` file of `size` bytes after `padding` bytes of `0`.
+    fn write_m4s(path: &Path, padding: usize, size: usize) {
+        let data = [vec![b'0'; padding], mp4_box(b"ftyp", &vec![0; size - 8])].concat();
+        fs::write(path, data).unwrap();
+    }
+
+    fn get_dash(audio_id: u32) -> JsonPlayUrlDash {
+        serde_json::from_str(&format!(r#"{{"audio": [{{"id": {audio_id}}}]}}"#)).unwrap()
+    }
+
+    #[test]
+    fn subtitle_languages_exclude_the_media_directory() {
+        let path = get_temporary_path("subtitle_languages");
+        write_entry(&path, 1, "");
+        for (directory, file) in [
+            ("en", "subtitle.json"),
+            ("zh-Hans", "subtitle.ass"),
+            // The media directory has JSON files too.
+            ("64", "index.json"),
+            ("empty", "cover.jpg"),
+        ] {
+            fs::create_dir_all(path.join(directory)).unwrap();
+            fs::write(path.join(directory).join(file), "").unwrap();
+        }
+
+        let subtitle_languages =
+            EpisodeMetadata::new_from_path(&path).and_then(|e| e.get_subtitle_languages());
+        fs::remove_dir_all(&path).unwrap();
+
+        assert_eq!(subtitle_languages.unwrap(), ["en", "zh-Hans"]);
+    }
+
+    #[test]
+    fn invalid_episodes_are_skipped_and_kept() {
+        let path = get_temporary_path("invalid_episodes");
+        write_entry(&path.join("ss1").join("ep1"), 1, "");
+        fs::create_dir_all(path.join("ss1").join("ep2")).unwrap();
+        fs::write(path.join("ss1").join("ep2").join("entry.json"), "{").unwrap();
+        fs::create_dir_all(path.join("ss2").join("ep1")).unwrap();
+        fs::create_dir_all(path.join("pc")).unwrap();
+        fs::write(path.join("pc").join(".videoInfo"), "{}").unwrap();
+        fs::write(path.join("ss1").join("cover.jpg"), "").unwrap();
+        fs::write(path.join("notes.txt"), "").unwrap();
+
+        let download_folder = DownloadFolder::new_from_path(&path);
+        fs::remove_dir_all(&path).unwrap();
+        let download_folder = download_folder.unwrap();
+
+        let [anime, ss2] = download_folder.seasons.as_slice() else {
+            panic!("{:?}", download_folder.seasons);
+        };
+        assert_eq!(anime.title, "Anime");
+        assert_eq!(anime.episodes.len(), 1);
+        assert_eq!(anime.invalid_episodes[0].path, path.join("ss1").join("ep2"));
+        // Without a readable episode, the directory name is the title.
+        assert_eq!(ss2.title, "ss2");
+        assert!(ss2.episodes.is_empty());
+        assert_eq!(download_folder.invalid_episodes[0].path, path.join("pc"));
+
+        let invalid_paths: Vec<_> = download_folder
+            .get_invalid_episodes()
+            .map(|e| e.path.clone())
+            .collect();
+        assert_eq!(
+            invalid_paths,
+            vec![
+                path.join("ss1").join("ep2"),
+                path.join("ss2").join("ep1"),
+                path.join("pc")
+            ]
+        );
+    }
+
+    #[test]
+    fn segments_are_in_numeric_order() {
+        let path = get_temporary_path("segments");
+        write_entry(&path, 1, "");
+        let media_path = path.join("64");
+        fs::create_dir_all(&media_path).unwrap();
+        // `10.blv` comes before `2.blv` by name.
+        for i in (0..=10).rev() {
+            fs::write(media_path.join(format!("{i}.blv")), "").unwrap();
+        }
+        let episode = EpisodeMetadata::new_from_path(&path).unwrap();
+        let expected: Vec<PathBuf> = (0..=10)
+            .map(|i| media_path.join(format!("{i}.blv")))
+            .collect();
+
+        let without_index = episode.get_media_layout();
+        fs::write(
+            media_path.join("index.json"),
+            format!(r#"{{"segment_list": [{}]}}"#, ["{}"; 11].join(",")),
+        )
+        .unwrap();
+        let with_index = episode.get_media_layout();
+        fs::remove_dir_all(&path).unwrap();
+
+        assert_eq!(
+            without_index.unwrap(),
+            MediaLayout::Segmented(expected.clone())
+        );
+        assert_eq!(with_index.unwrap(), MediaLayout::Segmented(expected));
+    }
+
+    #[test]
+    fn pc_streams_are_found_with_their_padding() {
+        let path = get_temporary_path("pc_media_layout");
+        fs::create_dir_all(&path).unwrap();
+        write_m4s(&path.join("5-1-30080.m4s"), 9, 100);
+        // Not in the usual range of audio ids, so only `.playurl` tells it's audio.
+        write_m4s(&path.join("5-1-30380.m4s"), 0, 50);
+        fs::write(path.join(".videoInfo"), "{}").unwrap();
+        fs::write(path.join("cover.jpg"), "").unwrap();
+
+        let with_playurl = get_pc_media_layout(&path, Some(&get_dash(30380)));
+        let without_playurl = get_pc_media_layout(&path, None);
+        fs::remove_dir_all(&path).unwrap();
+
+        assert_eq!(
+            with_playurl.unwrap(),
+            Some(MediaLayout::Dash {
+                video_path: path.join("5-1-30080.m4s"),
+                audio_path: path.join("5-1-30380.m4s"),
+                video_padding: 9,
+                audio_padding: 0,
+            })
+        );
+        assert!(matches!(without_playurl, Err(Error::InputError(_))));
+    }
+
+    #[test]
+    fn pc_streams_are_optional() {
+        let path = get_temporary_path("pc_media_layout_empty");
+        fs::create_dir_all(&path).unwrap();
+        fs::write(path.join("0.blv"), "").unwrap();
+
+        let layout = get_pc_media_layout(&path, None);
+        fs::remove_dir_all(&path).unwrap();
+
+        assert_eq!(layout.unwrap(), None);
+        assert!(matches!(
+            get_pc_media_layout(&path, None),
+            Err(Error::InputError(_))
+        ));
+    }
+
+    #[test]
+    fn entry_download_status_is_read() {
+        let path = get_temporary_path("entry_download_status");
+        let cases = [
+            (
+                r#", "is_completed": true, "downloaded_bytes": 5, "total_bytes": 10"#,
+                DownloadStatus::Completed,
+            ),
+            (
+                r#", "is_completed": false, "downloaded_bytes": 5, "total_bytes": 10"#,
+                DownloadStatus::Incomplete {
+                    downloaded_bytes: Some(5),
+                    total_bytes: Some(10),
+                },
+            ),
+            // The bytes are only compared without `is_completed`.
+            (
+                r#", "downloaded_bytes": 10, "total_bytes": 10"#,
+                DownloadStatus::Completed,
+            ),
+            (
+                r#", "downloaded_bytes": 5, "total_bytes": 10"#,
+                DownloadStatus::Incomplete {
+                    downloaded_bytes: Some(5),
+                    total_bytes: Some(10),
+                },
+            ),
+            (
+                r#", "downloaded_bytes": 0, "total_bytes": 0"#,
+                DownloadStatus::Unknown,
+            ),
+            ("", DownloadStatus::Unknown),
+        ];
+
+        let statuses: Vec<_> = cases
+            .iter()
+            .map(|(extra, _)| {
+                write_entry(&path, 1, extra);
+                EpisodeMetadata::new_from_path(&path).map(|e| e.download_status)
+            })
+            .collect();
+        fs::remove_dir_all(&path).unwrap();
+
+        for (status, (extra, expected)) in statuses.into_iter().zip(cases) {
+            assert_eq!(status.unwrap(), expected, "{extra}");
         }
     }
+
+    #[test]
+    fn pc_download_status_compares_the_stream_sizes() {
+        let path = get_temporary_path("pc_download_status");
+        fs::create_dir_all(&path).unwrap();
+        write_m4s(&path.join("5-1-30080.m4s"), 9, 100);
+        write_m4s(&path.join("5-1-30280.m4s"), 9, 50);
+        let get_status = |status: &str, video_size: &str| {
+            fs::write(
+                path.join(".videoInfo"),
+                format!(r#"{{"groupTitle": "Show", "p": 1{status}}}"#),
+            )
+            .unwrap();
+            fs::write(
+                path.join(".playurl"),
+                format!(
+                    r#"{{"data": {{"dash": {{"video": [{{"id": 80{video_size}}}], "audio": [{{"id": 30280, "size": 50}}]}}}}}}"#
+                ),
+            )
+            .unwrap();
+
+            EpisodeMetadata::new_from_path(&path).map(|e| e.download_status)
+        };
+        let incomplete = DownloadStatus::Incomplete {
+            downloaded_bytes: Some(150),
+            total_bytes: Some(200),
+        };
+
+        let statuses = [
+            get_status(r#", "status": "completed""#, r#", "size": 100"#),
+            get_status(r#", "status": "completed""#, r#", "size": 150"#),
+            get_status(r#", "status": "downloading""#, r#", "size": 150"#),
+            get_status("", r#", "size": 100"#),
+            get_status("", r#", "size": 150"#),
+            // Without the size of every stream, the recorded status is kept.
+            get_status(r#", "status": "completed""#, ""),
+            get_status("", ""),
+        ];
+        fs::remove_dir_all(&path).unwrap();
+
+        assert_eq!(
+            statuses.map(|s| s.unwrap()),
+            [
+                DownloadStatus::Completed,
+                incomplete,
+                incomplete,
+                DownloadStatus::Completed,
+                incomplete,
+                DownloadStatus::Completed,
+                DownloadStatus::Unknown,
+            ]
+        );
+    }
 }
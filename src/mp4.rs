@@ -0,0 +1,1215 @@
+use crate::error::{Error, Result};
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufReader, Read, Seek, SeekFrom},
+    path::Path,
+};
+
+/// A track inside a fragmented MP4 file, e.g. Bilibili's `video.m4s` or `audio.m4s`.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Mp4Track {
+    pub track_id: u32,
+    pub kind: Mp4TrackKind,
+    pub codec: Mp4Codec,
+    pub timescale: u32,
+    /// Media time the presentation starts at, taken from the edit list.
+    pub media_time: i64,
+    pub samples: Vec<Mp4Sample>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Mp4TrackKind {
+    Video { width: u32, height: u32 },
+    Audio { sample_rate: f64, channels: u16 },
+}
+
+/// Codec of a track, with its decoder configuration if the codec needs one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Mp4Codec {
+    Avc(Vec<u8>),
+    Hevc(Vec<u8>),
+    Av1(Vec<u8>),
+    Aac(Vec<u8>),
+    Ac3,
+    Eac3,
+    Flac(Vec<u8>),
+}
+
+/// Location and timing of a sample. Times are in the track's timescale.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Mp4Sample {
+    pub offset: u64,
+    pub size: u32,
+    pub decode_time: i64,
+    pub composition_offset: i64,
+    pub duration: u32,
+    pub is_keyframe: bool,
+}
+
+/// Defaults of a track set by `trex` and `tfhd` boxes.
+#[derive(Debug, Clone, Copy, Default)]
+struct SampleDefaults {
+    duration: u32,
+    size: u32,
+    flags: u32,
+}
+
+/// Header of an MP4 box.
+#[derive(Debug, Clone, Copy)]
+struct BoxHeader {
+    kind: [u8; 4],
+    start: u64,
+    size: u64,
+    header_size: u64,
+    /// Position right after the box.
+    end: u64,
+}
+
+/// Big-endian reader over the payload of a box.
+struct BoxReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl Mp4Sample {
+    /// Presentation time of the sample.
+    pub fn get_presentation_time(&self) -> i64 {
+        self.decode_time + self.composition_offset
+    }
+}
+
+impl Mp4Track {
+    /// Read every track of a fragmented MP4 file. Only the boxes are read, sample data stays in
+    /// the file.
+    pub fn read_tracks(path: impl AsRef<Path>) -> Result<Vec<Self>> {
+        let path = path.as_ref();
+        let mut reader = BufReader::new(File::open(path)?);
+        let file_size = reader.seek(SeekFrom::End(0))?;
+        reader.seek(SeekFrom::Start(0))?;
+
+        let mut tracks: Vec<Self> = vec![];
+        let mut trex: HashMap<u32, SampleDefaults> = HashMap::new();
+        let mut next_decode_time: HashMap<u32, i64> = HashMap::new();
+
+        while let Some(header) = read_box_header(&mut reader, file_size)? {
+            match &header.kind {
+                b"moov" => {
+                    let data = read_box_payload(&mut reader, &header)?;
+                    parse_moov(&data, &mut tracks, &mut trex)?;
+                }
+                b"moof" => {
+                    let data = read_box_payload(&mut reader, &header)?;
+                    parse_moof(
+                        &data,
+                        header.start,
+                        &mut tracks,
+                        &trex,
+                        &mut next_decode_time,
+                    )?;
+                }
+                _ => {
+                    reader.seek(SeekFrom::Start(header.end))?;
+                }
+            }
+        }
+
+        if tracks.is_empty() {
+            return Err(format!("No tracks found in {}.", path.display()).into());
+        }
+
+        Ok(tracks)
+    }
+
+    /// Read the width and height of the first video track. Only the `moov` box at the start of
+    /// the file is read. Boxes start at `start`, which skips the padding of PC client files.
+    pub fn read_video_resolution(path: impl AsRef<Path>, start: u64) -> Result<Option<(u32, u32)>> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let file_size = reader.seek(SeekFrom::End(0))?;
+        reader.seek(SeekFrom::Start(start))?;
+
+        while let Some(header) = read_box_header(&mut reader, file_size)? {
+            if &header.kind != b"moov" {
+                reader.seek(SeekFrom::Start(header.end))?;
+                continue;
+            }
+
+            let data = read_box_payload(&mut reader, &header)?;
+            let mut tracks = vec![];
+            parse_moov(&data, &mut tracks, &mut HashMap::new())?;
+
+            return Ok(tracks.iter().find_map(|t| match t.kind {
+                Mp4TrackKind::Video { width, height } if width > 0 && height > 0 => {
+                    Some((width, height))
+                }
+                _ => None,
+            }));
+        }
+
+        Ok(None)
+    }
+}
+
+fn read_box_header(reader: &mut (impl Read + Seek), file_size: u64) -> Result<Option<BoxHeader>> {
+    let start = reader.stream_position()?;
+
+    if file_size.saturating_sub(start) < 8 {
+        return Ok(None);
+    }
+
+    let mut buffer = [0; 8];
+    reader.read_exact(&mut buffer)?;
+
+    let kind = [buffer[4], buffer[5], buffer[6], buffer[7]];
+    let (size, header_size) = match u32::from_be_bytes([buffer[0], buffer[1], buffer[2], buffer[3]])
+    {
+        0 => (file_size - start, 8),
+        1 => {
+            let mut large_size = [0; 8];
+            reader.read_exact(&mut large_size)?;
+
+            (u64::from_be_bytes(large_size), 16)
+        }
+        v => (v as u64, 8),
+    };
+
+    // Sizes past the end of the file come from truncated or corrupt files, and are rejected
+    // before anything is allocated for them.
+    let end = match start.checked_add(size) {
+        Some(end) if size >= header_size && end <= file_size => end,
+        _ => {
+            return Err(Error::InputError(format!(
+                "Invalid size of MP4 box at {start}: {size} bytes, but only {} are left.",
+                file_size - start
+            )))
+        }
+    };
+
+    Ok(Some(BoxHeader {
+        kind,
+        start,
+        size,
+        header_size,
+        end,
+    }))
+}
+
+fn read_box_payload(reader: &mut impl Read, header: &BoxHeader) -> Result<Vec<u8>> {
+    let length = usize::try_from(header.size - header.header_size)
+        .map_err(|_| Error::InputError(format!("MP4 box at {} is too large.", header.start)))?;
+    let mut data = vec![0; length];
+    reader.read_exact(&mut data)?;
+
+    Ok(data)
+}
+
+/// Iterate the child boxes inside a payload. Yields the box type and its payload.
+fn children(data: &[u8]) -> impl Iterator<Item = ([u8; 4], &[u8])> {
+    let mut position = 0;
+
+    std::iter::from_fn(move || {
+        if position + 8 > data.len() {
+            return None;
+        }
+
+        let mut reader = BoxReader::new(&data[position..]);
+        let size = reader.u32().ok()? as usize;
+        let kind = reader.kind().ok()?;
+        let (size, header_size) = match size {
+            0 => (data.len() - position, 8),
+            1 => (reader.u64().ok()? as usize, 16),
+            v => (v, 8),
+        };
+
+        if size < header_size || position.checked_add(size)? > data.len() {
+            return None;
+        }
+
+        let payload = &data[position + header_size..position + size];
+        position += size;
+
+        Some((kind, payload))
+    })
+}
+
+fn find_child<'a>(data: &'a [u8], kind: &[u8; 4]) -> Option<&'a [u8]> {
+    children(data).find(|(k, _)| k == kind).map(|(_, p)| p)
+}
+
+fn parse_moov(
+    data: &[u8],
+    tracks: &mut Vec<Mp4Track>,
+    trex: &mut HashMap<u32, SampleDefaults>,
+) -> Result<()> {
+    for (kind, payload) in children(data) {
+        match &kind {
+            b"trak" => {
+                if let Some(track) = parse_trak(payload)? {
+                    tracks.push(track);
+                }
+            }
+            b"mvex" => {
+                for (_, payload) in children(payload).filter(|(k, _)| k == b"trex") {
+                    let mut reader = BoxReader::new(payload);
+                    reader.skip(4)?; // version and flags
+                    let track_id = reader.u32()?;
+                    reader.skip(4)?; // default_sample_description_index
+
+                    trex.insert(
+                        track_id,
+                        SampleDefaults {
+                            duration: reader.u32()?,
+                            size: reader.u32()?,
+                            flags: reader.u32()?,
+                        },
+                    );
+                }
+            }
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+/// Parse a track. Returns `None` for tracks that are neither video nor audio.
+fn parse_trak(data: &[u8]) -> Result<Option<Mp4Track>> {
+    let tkhd = find_child(data, b"tkhd").ok_or("MP4 track has no tkhd box.")?;
+    let mdia = find_child(data, b"mdia").ok_or("MP4 track has no mdia box.")?;
+    let mdhd = find_child(mdia, b"mdhd").ok_or("MP4 track has no mdhd box.")?;
+    let hdlr = find_child(mdia, b"hdlr").ok_or("MP4 track has no hdlr box.")?;
+    let stsd = find_child(mdia, b"minf")
+        .and_then(|m| find_child(m, b"stbl"))
+        .and_then(|s| find_child(s, b"stsd"))
+        .ok_or("MP4 track has no stsd box.")?;
+
+    let mut reader = BoxReader::new(tkhd);
+    let version = reader.u8()?;
+    reader.skip(3)?; // flags
+    reader.skip(if version == 1 { 16 } else { 8 })?; // creation and modification time
+    let track_id = reader.u32()?;
+    reader.skip(4)?; // reserved
+    reader.skip(if version == 1 { 8 } else { 4 })?; // duration
+    reader.skip(52)?; // reserved, layer, alternate group, volume, reserved, and matrix
+    let width = reader.u32()? >> 16;
+    let height = reader.u32()? >> 16;
+
+    let mut reader = BoxReader::new(mdhd);
+    let version = reader.u8()?;
+    reader.skip(3)?; // flags
+    reader.skip(if version == 1 { 16 } else { 8 })?; // creation and modification time
+    let timescale = reader.u32()?;
+
+    let mut reader = BoxReader::new(hdlr);
+    reader.skip(8)?; // version, flags, and pre_defined
+    let handler = reader.kind()?;
+
+    let media_time = find_child(data, b"edts")
+        .and_then(|e| find_child(e, b"elst"))
+        .map(parse_elst)
+        .transpose()?
+        .unwrap_or_default();
+
+    let mut reader = BoxReader::new(stsd);
+    reader.skip(8)?; // version, flags, and entry_count
+    let (entry_kind, entry) = children(reader.rest())
+        .next()
+        .ok_or("MP4 track has no sample entry.")?;
+
+    let (kind, codec) = match &handler {
+        b"vide" => (
+            Mp4TrackKind::Video { width, height },
+            parse_video_sample_entry(&entry_kind, entry)?,
+        ),
+        b"soun" => parse_audio_sample_entry(&entry_kind, entry)?,
+        _ => return Ok(None),
+    };
+
+    Ok(Some(Mp4Track {
+        track_id,
+        kind,
+        codec,
+        timescale,
+        media_time,
+        samples: vec![],
+    }))
+}
+
+/// Get the media time of the first edit that isn't empty.
+fn parse_elst(data: &[u8]) -> Result<i64> {
+    let mut reader = BoxReader::new(data);
+    let version = reader.u8()?;
+    reader.skip(3)?; // flags
+    let entry_count = reader.u32()?;
+
+    for _ in 0..entry_count {
+        let media_time = match version {
+            1 => {
+                reader.skip(8)?; // segment_duration
+                reader.u64()? as i64
+            }
+            _ => {
+                reader.skip(4)?; // segment_duration
+                reader.u32()? as i32 as i64
+            }
+        };
+        reader.skip(4)?; // media_rate
+
+        if media_time >= 0 {
+            return Ok(media_time);
+        }
+    }
+
+    Ok(0)
+}
+
+fn parse_video_sample_entry(kind: &[u8; 4], data: &[u8]) -> Result<Mp4Codec> {
+    // Fields of a visual sample entry before its child boxes.
+    let boxes = data
+        .get(78..)
+        .ok_or("MP4 video sample entry is too short.")?;
+    let get_config = |config_kind: &[u8; 4]| {
+        find_child(boxes, config_kind)
+            .map(|c| c.to_vec())
+            .ok_or(format!(
+                "MP4 video sample entry has no {} box.",
+                String::from_utf8_lossy(config_kind)
+            ))
+    };
+
+    match kind {
+        b"avc1" | b"avc3" => Ok(Mp4Codec::Avc(get_config(b"avcC")?)),
+        b"hev1" | b"hvc1" => Ok(Mp4Codec::Hevc(get_config(b"hvcC")?)),
+        b"av01" => Ok(Mp4Codec::Av1(get_config(b"av1C")?)),
+        _ => Err(format!(
+            "Unsupported MP4 video codec: {}",
+            String::from_utf8_lossy(kind)
+        )
+        .into()),
+    }
+}
+
+fn parse_audio_sample_entry(kind: &[u8; 4], data: &[u8]) -> Result<(Mp4TrackKind, Mp4Codec)> {
+    let mut reader = BoxReader::new(data);
+    reader.skip(8)?; // reserved and data_reference_index
+    let version = reader.u16()?;
+    reader.skip(6)?; // revision level and vendor
+    let channels = reader.u16()?;
+    reader.skip(6)?; // sample size, compression id, and packet size
+    let sample_rate = reader.u32()? >> 16;
+    // QuickTime sound sample descriptions have extra fields.
+    reader.skip(match version {
+        1 => 16,
+        2 => 36,
+        _ => 0,
+    })?;
+    let boxes = reader.rest();
+
+    let codec = match kind {
+        b"mp4a" => {
+            let esds = find_child(boxes, b"esds").ok_or("MP4 audio has no esds box.")?;
+
+            Mp4Codec::Aac(parse_esds(esds)?)
+        }
+        b"ac-3" => Mp4Codec::Ac3,
+        b"ec-3" => Mp4Codec::Eac3,
+        b"fLaC" => {
+            let dfla = find_child(boxes, b"dfLa").ok_or("MP4 audio has no dfLa box.")?;
+            let mut codec_private = b"fLaC".to_vec();
+            codec_private.extend_from_slice(dfla.get(4..).unwrap_or_default());
+
+            Mp4Codec::Flac(codec_private)
+        }
+        _ => {
+            return Err(format!(
+                "Unsupported MP4 audio codec: {}",
+                String::from_utf8_lossy(kind)
+            )
+            .into())
+        }
+    };
+
+    Ok((
+        Mp4TrackKind::Audio {
+            sample_rate: sample_rate as f64,
+            channels,
+        },
+        codec,
+    ))
+}
+
+/// Get the `AudioSpecificConfig` inside an `esds` box.
+fn parse_esds(data: &[u8]) -> Result<Vec<u8>> {
+    let mut reader = BoxReader::new(data);
+    reader.skip(4)?; // version and flags
+
+    while !reader.rest().is_empty() {
+        let tag = reader.u8()?;
+        let length = reader.descriptor_length()?;
+
+        match tag {
+            // ES_Descriptor
+            0x03 => {
+                reader.skip(2)?; // ES_ID
+                let flags = reader.u8()?;
+                if flags & 0x80 != 0 {
+                    reader.skip(2)?; // dependsOn_ES_ID
+                }
+                if flags & 0x40 != 0 {
+                    let url_length = reader.u8()? as usize;
+                    reader.skip(url_length)?;
+                }
+                if flags & 0x20 != 0 {
+                    reader.skip(2)?; // OCR_ES_Id
+                }
+            }
+            // DecoderConfigDescriptor
+            0x04 => reader.skip(13)?,
+            // DecoderSpecificInfo
+            0x05 => return Ok(reader.bytes(length)?.to_vec()),
+            _ => reader.skip(length)?,
+        }
+    }
+
+    Err("MP4 audio has no AudioSpecificConfig.".into())
+}
+
+fn parse_moof(
+    data: &[u8],
+    moof_start: u64,
+    tracks: &mut [Mp4Track],
+    trex: &HashMap<u32, SampleDefaults>,
+    next_decode_time: &mut HashMap<u32, i64>,
+) -> Result<()> {
+    for traf in children(data).filter(|(k, _)| k == b"traf").map(|(_, p)| p) {
+        let tfhd = find_child(traf, b"tfhd").ok_or("MP4 fragment has no tfhd box.")?;
+        let mut reader = BoxReader::new(tfhd);
+        let flags = reader.u32()? & 0xFFFFFF;
+        let track_id = reader.u32()?;
+        let mut defaults = trex.get(&track_id).copied().unwrap_or_default();
+
+        let base_data_offset = match flags & 0x1 {
+            0 => moof_start,
+            _ => reader.u64()?,
+        };
+        if flags & 0x2 != 0 {
+            reader.skip(4)?; // sample_description_index
+        }
+        if flags & 0x8 != 0 {
+            defaults.duration = reader.u32()?;
+        }
+        if flags & 0x10 != 0 {
+            defaults.size = reader.u32()?;
+        }
+        if flags & 0x20 != 0 {
+            defaults.flags = reader.u32()?;
+        }
+
+        let Some(track) = tracks.iter_mut().find(|t| t.track_id == track_id) else {
+            continue;
+        };
+        let is_video = matches!(track.kind, Mp4TrackKind::Video { .. });
+
+        let mut decode_time = match find_child(traf, b"tfdt") {
+            Some(tfdt) => {
+                let mut reader = BoxReader::new(tfdt);
+                match reader.u8()? {
+                    1 => {
+                        reader.skip(3)?;
+                        reader.u64()? as i64
+                    }
+                    _ => {
+                        reader.skip(3)?;
+                        reader.u32()? as i64
+                    }
+                }
+            }
+            None => next_decode_time.get(&track_id).copied().unwrap_or_default(),
+        };
+        let mut data_offset = base_data_offset;
+
+        for trun in children(traf).filter(|(k, _)| k == b"trun").map(|(_, p)| p) {
+            let mut reader = BoxReader::new(trun);
+            let version = reader.u8()?;
+            let flags = reader.u24()?;
+            let sample_count = reader.u32()?;
+
+            if flags & 0x1 != 0 {
+                data_offset = base_data_offset.wrapping_add_signed(reader.u32()? as i32 as i64);
+            }
+            let first_sample_flags = match flags & 0x4 {
+                0 => None,
+                _ => Some(reader.u32()?),
+            };
+
+            for i in 0..sample_count {
+                let duration = match flags & 0x100 {
+                    0 => defaults.duration,
+                    _ => reader.u32()?,
+                };
+                let size = match flags & 0x200 {
+                    0 => defaults.size,
+                    _ => reader.u32()?,
+                };
+                let sample_flags = match flags & 0x400 {
+                    0 => defaults.flags,
+                    _ => reader.u32()?,
+                };
+                let sample_flags = match (i, first_sample_flags) {
+                    (0, Some(f)) => f,
+                    _ => sample_flags,
+                };
+                let composition_offset = match (flags & 0x800, version) {
+                    (0, _) => 0,
+                    (_, 0) => reader.u32()? as i64,
+                    _ => reader.u32()? as i32 as i64,
+                };
+
+                track.samples.push(Mp4Sample {
+                    offset: data_offset,
+                    size,
+                    decode_time,
+                    composition_offset,
+                    duration,
+                    // `sample_is_non_sync_sample` flag.
+                    is_keyframe: !is_video || sample_flags & 0x10000 == 0,
+                });
+
+                data_offset = data_offset.saturating_add(size as u64);
+                decode_time = decode_time.saturating_add(duration as i64);
+            }
+        }
+
+        next_decode_time.insert(track_id, decode_time);
+    }
+
+    Ok(())
+}
+
+impl<'a> BoxReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, position: 0 }
+    }
+
+    fn bytes(&mut self, length: usize) -> Result<&'a [u8]> {
+        let bytes = self
+            .data
+            .get(self.position..self.position.saturating_add(length))
+            .ok_or("MP4 box is too short.")?;
+        self.position += length;
+
+        Ok(bytes)
+    }
+
+    fn skip(&mut self, length: usize) -> Result<()> {
+        self.bytes(length).map(|_| ())
+    }
+
+    fn rest(&self) -> &'a [u8] {
+        self.data.get(self.position..).unwrap_or_default()
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        let b = self.bytes(2)?;
+
+        Ok(u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    fn u24(&mut self) -> Result<u32> {
+        let b = self.bytes(3)?;
+
+        Ok(u32::from_be_bytes([0, b[0], b[1], b[2]]))
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        let b = self.bytes(4)?;
+
+        Ok(u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    fn u64(&mut self) -> Result<u64> {
+        let b = self.bytes(8)?;
+
+        Ok(u64::from_be_bytes([
+            b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7],
+        ]))
+    }
+
+    fn kind(&mut self) -> Result<[u8; 4]> {
+        let b = self.bytes(4)?;
+
+        Ok([b[0], b[1], b[2], b[3]])
+    }
+
+    /// Read the variable length of an MPEG-4 descriptor.
+    fn descriptor_length(&mut self) -> Result<usize> {
+        let mut length = 0;
+
+        for _ in 0..4 {
+            let b = self.u8()?;
+            length = (length << 7) | (b & 0x7F) as usize;
+
+            if b & 0x80 == 0 {
+                break;
+            }
+        }
+
+        Ok(length)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::{fs, io::Cursor, path::PathBuf};
+
+    pub(crate) const AVC_CONFIG: &[u8] = &[1, 0x64, 0, 0x1F, 0xFF, 0xE1, 0, 4, 1, 2, 3, 4];
+    pub(crate) const AAC_CONFIG: &[u8] = &[0x11, 0x90];
+
+    const SYNC_SAMPLE: u32 = 0x2000000;
+    const NON_SYNC_SAMPLE: u32 = 0x1010000;
+
+    /// A sample of an MP4 file built by `fragmented_mp4`. Times are in milliseconds.
+    #[derive(Debug, Clone)]
+    pub(crate) struct TestSample {
+        pub duration: u32,
+        pub composition_offset: i32,
+        pub is_keyframe: bool,
+        pub data: Vec<u8>,
+    }
+
+    impl TestSample {
+        pub(crate) fn new(duration: u32, is_keyframe: bool, data: &[u8]) -> Self {
+            Self {
+                duration,
+                composition_offset: 0,
+                is_keyframe,
+                data: data.to_vec(),
+            }
+        }
+    }
+
+    /// Get a path inside the temporary directory that is unique to this test process.
+    pub(crate) fn get_temporary_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("bilibili-extractor-{}-{name}", std::process::id()))
+    }
+
+    pub(crate) fn mp4_box(kind: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+        let mut data = ((payload.len() + 8) as u32).to_be_bytes().to_vec();
+        data.extend_from_slice(kind);
+        data.extend_from_slice(payload);
+
+        data
+    }
+
+    pub(crate) fn full_box(kind: &[u8; 4], version: u8, flags: u32, payload: &[u8]) -> Vec<u8> {
+        let mut data = vec![version];
+        data.extend_from_slice(&flags.to_be_bytes()[1..]);
+        data.extend_from_slice(payload);
+
+        mp4_box(kind, &data)
+    }
+
+    /// Build a fragmented MP4 file with one track, with a timescale of 1000 and one `moof` and
+    /// `mdat` for every fragment.
+    pub(crate) fn fragmented_mp4(
+        is_video: bool,
+        media_time: Option<i32>,
+        fragments: &[Vec<TestSample>],
+    ) -> Vec<u8> {
+        let mut data = mp4_box(b"ftyp", b"iso5\0\0\0\x01iso5dash");
+        data.extend(moov(is_video, media_time));
+
+        let mut decode_time = 0;
+
+        for (i, samples) in fragments.iter().enumerate() {
+            // The data offset is relative to the `moof`, and points after the `mdat` header.
+            let moof_size = moof(i as u32, decode_time, samples, 0).len();
+            data.extend(moof(i as u32, decode_time, samples, moof_size as i32 + 8));
+            data.extend(mp4_box(
+                b"mdat",
+                &samples
+                    .iter()
+                    .flat_map(|s| s.data.clone())
+                    .collect::<Vec<_>>(),
+            ));
+
+            decode_time += samples.iter().map(|s| s.duration as u64).sum::<u64>();
+        }
+
+        data
+    }
+
+    fn moov(is_video: bool, media_time: Option<i32>) -> Vec<u8> {
+        let mut tkhd = vec![0; 8]; // creation and modification time
+        tkhd.extend(1u32.to_be_bytes()); // track_ID
+        tkhd.extend([0; 8]); // reserved and duration
+        tkhd.extend([0; 52]); // reserved, layer, alternate group, volume, reserved, and matrix
+        tkhd.extend((640u32 << 16).to_be_bytes());
+        tkhd.extend((360u32 << 16).to_be_bytes());
+
+        let mut mdhd = vec![0; 8]; // creation and modification time
+        mdhd.extend(1000u32.to_be_bytes()); // timescale
+        mdhd.extend([0; 8]); // duration, language, and pre_defined
+
+        let mut hdlr = vec![0; 4]; // pre_defined
+        hdlr.extend_from_slice(if is_video { b"vide" } else { b"soun" });
+        hdlr.extend([0; 13]); // reserved and name
+
+        let sample_entry = match is_video {
+            true => {
+                let mut avc1 = vec![0; 78];
+                avc1.extend(mp4_box(b"avcC", AVC_CONFIG));
+
+                mp4_box(b"avc1", &avc1)
+            }
+            false => {
+                let mut mp4a = vec![0; 8]; // reserved and data_reference_index
+                mp4a.extend([0; 8]); // version, revision level, and vendor
+                mp4a.extend(2u16.to_be_bytes()); // channelcount
+                mp4a.extend([0; 6]); // samplesize, compression id, and packet size
+                mp4a.extend((48000u32 << 16).to_be_bytes());
+
+                let mut esds = vec![0x03, 25, 0, 1, 0]; // ES_Descriptor
+                esds.extend([0x04, 17, 0x40, 0x15]); // DecoderConfigDescriptor
+                esds.extend([0; 11]);
+                esds.extend([0x05, AAC_CONFIG.len() as u8]); // DecoderSpecificInfo
+                esds.extend_from_slice(AAC_CONFIG);
+                mp4a.extend(full_box(b"esds", 0, 0, &esds));
+
+                mp4_box(b"mp4a", &mp4a)
+            }
+        };
+        let mut stsd = 1u32.to_be_bytes().to_vec();
+        stsd.extend(sample_entry);
+
+        let mdia = [
+            full_box(b"mdhd", 0, 0, &mdhd),
+            full_box(b"hdlr", 0, 0, &hdlr),
+            mp4_box(b"minf", &mp4_box(b"stbl", &full_box(b"stsd", 0, 0, &stsd))),
+        ]
+        .concat();
+
+        let mut trak = full_box(b"tkhd", 0, 3, &tkhd);
+        if let Some(media_time) = media_time {
+            let mut elst = 1u32.to_be_bytes().to_vec();
+            elst.extend(0u32.to_be_bytes()); // segment_duration
+            elst.extend(media_time.to_be_bytes());
+            elst.extend(0x10000u32.to_be_bytes()); // media_rate
+
+            trak.extend(mp4_box(b"edts", &full_box(b"elst", 0, 0, &elst)));
+        }
+        trak.extend(mp4_box(b"mdia", &mdia));
+
+        let mut trex = 1u32.to_be_bytes().to_vec(); // track_ID
+        trex.extend(1u32.to_be_bytes()); // default_sample_description_index
+        trex.extend([0; 12]); // default duration, size, and flags
+
+        mp4_box(
+            b"moov",
+            &[
+                mp4_box(b"trak", &trak),
+                mp4_box(b"mvex", &full_box(b"trex", 0, 0, &trex)),
+            ]
+            .concat(),
+        )
+    }
+
+    /// Build a `moof` whose `trun` sets every field of every sample.
+    fn moof(sequence: u32, decode_time: u64, samples: &[TestSample], data_offset: i32) -> Vec<u8> {
+        let mut trun = (samples.len() as u32).to_be_bytes().to_vec();
+        trun.extend(data_offset.to_be_bytes());
+
+        for s in samples {
+            trun.extend(s.duration.to_be_bytes());
+            trun.extend((s.data.len() as u32).to_be_bytes());
+            trun.extend(match s.is_keyframe {
+                true => SYNC_SAMPLE.to_be_bytes(),
+                false => NON_SYNC_SAMPLE.to_be_bytes(),
+            });
+            trun.extend(s.composition_offset.to_be_bytes());
+        }
+
+        let traf = [
+            // default-base-is-moof
+            full_box(b"tfhd", 0, 0x20000, &1u32.to_be_bytes()),
+            full_box(b"tfdt", 1, 0, &decode_time.to_be_bytes()),
+            full_box(b"trun", 1, 0xF01, &trun),
+        ]
+        .concat();
+
+        mp4_box(
+            b"moof",
+            &[
+                full_box(b"mfhd", 0, 0, &sequence.to_be_bytes()),
+                mp4_box(b"traf", &traf),
+            ]
+            .concat(),
+        )
+    }
+
+    fn new_track(is_video: bool) -> Mp4Track {
+        Mp4Track {
+            track_id: 1,
+            kind: match is_video {
+                true => Mp4TrackKind::Video {
+                    width: 640,
+                    height: 360,
+                },
+                false => Mp4TrackKind::Audio {
+                    sample_rate: 48000.,
+                    channels: 2,
+                },
+            },
+            codec: Mp4Codec::Ac3,
+            timescale: 1000,
+            media_time: 0,
+            samples: vec![],
+        }
+    }
+
+    /// Parse the payload of a `moof` into a track.
+    fn parse_test_moof(moof: &[u8], moof_start: u64, is_video: bool) -> Vec<Mp4Sample> {
+        let mut tracks = vec![new_track(is_video)];
+        parse_moof(
+            moof,
+            moof_start,
+            &mut tracks,
+            &HashMap::new(),
+            &mut HashMap::new(),
+        )
+        .unwrap();
+
+        tracks.remove(0).samples
+    }
+
+    #[test]
+    fn read_tracks_reads_every_fragment() {
+        let fragments = vec![
+            vec![
+                TestSample::new(40, true, b"key frame"),
+                TestSample {
+                    composition_offset: 40,
+                    ..TestSample::new(40, false, b"frame")
+                },
+            ],
+            vec![
+                TestSample::new(40, true, b"second key frame"),
+                TestSample::new(40, false, b"last"),
+            ],
+        ];
+        let data = fragmented_mp4(true, Some(80), &fragments);
+        let path = get_temporary_path("read_tracks.m4s");
+        fs::write(&path, &data).unwrap();
+
+        let tracks = Mp4Track::read_tracks(&path).unwrap();
+        let resolution = Mp4Track::read_video_resolution(&path, 0).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(tracks.len(), 1);
+        assert_eq!(resolution, Some((640, 360)));
+
+        let track = &tracks[0];
+        assert_eq!(
+            track.kind,
+            Mp4TrackKind::Video {
+                width: 640,
+                height: 360
+            }
+        );
+        assert_eq!(track.codec, Mp4Codec::Avc(AVC_CONFIG.to_vec()));
+        assert_eq!(track.timescale, 1000);
+        assert_eq!(track.media_time, 80);
+
+        let samples: Vec<_> = fragments.iter().flatten().collect();
+        assert_eq!(track.samples.len(), samples.len());
+
+        for (i, (sample, expected)) in track.samples.iter().zip(samples).enumerate() {
+            let offset = sample.offset as usize;
+
+            assert_eq!(&data[offset..offset + sample.size as usize], expected.data);
+            assert_eq!(sample.decode_time, i as i64 * 40);
+            assert_eq!(
+                sample.composition_offset,
+                expected.composition_offset as i64
+            );
+            assert_eq!(sample.is_keyframe, expected.is_keyframe);
+        }
+    }
+
+    #[test]
+    fn read_video_resolution_skips_padding() {
+        let mut data = vec![b'0'; 48];
+        data.extend(fragmented_mp4(
+            true,
+            None,
+            &[vec![TestSample::new(40, true, b"key")]],
+        ));
+        let path = get_temporary_path("read_video_resolution_padding.m4s");
+        fs::write(&path, &data).unwrap();
+
+        let resolution = Mp4Track::read_video_resolution(&path, 48);
+        let padded_resolution = Mp4Track::read_video_resolution(&path, 0);
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(resolution.unwrap(), Some((640, 360)));
+        assert!(padded_resolution.is_err());
+    }
+
+    #[test]
+    fn read_tracks_reads_audio() {
+        let data = fragmented_mp4(false, None, &[vec![TestSample::new(21, true, b"aac")]]);
+        let path = get_temporary_path("read_tracks_audio.m4s");
+        fs::write(&path, &data).unwrap();
+
+        let tracks = Mp4Track::read_tracks(&path).unwrap();
+        let resolution = Mp4Track::read_video_resolution(&path, 0).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(resolution, None);
+        assert_eq!(
+            tracks[0].kind,
+            Mp4TrackKind::Audio {
+                sample_rate: 48000.,
+                channels: 2
+            }
+        );
+        assert_eq!(tracks[0].codec, Mp4Codec::Aac(AAC_CONFIG.to_vec()));
+        assert_eq!(tracks[0].samples.len(), 1);
+    }
+
+    #[test]
+    fn parse_moof_uses_tfhd_defaults_and_base_data_offset() {
+        let mut tfhd = 1u32.to_be_bytes().to_vec();
+        tfhd.extend(5000u64.to_be_bytes()); // base_data_offset
+        tfhd.extend(40u32.to_be_bytes()); // default_sample_duration
+        tfhd.extend(100u32.to_be_bytes()); // default_sample_size
+        tfhd.extend(NON_SYNC_SAMPLE.to_be_bytes()); // default_sample_flags
+
+        let mut trun = 3u32.to_be_bytes().to_vec();
+        trun.extend(8i32.to_be_bytes()); // data_offset
+        trun.extend(SYNC_SAMPLE.to_be_bytes()); // first_sample_flags
+
+        let traf = [
+            full_box(b"tfhd", 0, 0x1 | 0x8 | 0x10 | 0x20, &tfhd),
+            full_box(b"trun", 0, 0x1 | 0x4, &trun),
+        ]
+        .concat();
+        let samples = parse_test_moof(&mp4_box(b"traf", &traf), 100, true);
+
+        assert_eq!(
+            samples.iter().map(|s| s.offset).collect::<Vec<_>>(),
+            vec![5008, 5108, 5208]
+        );
+        assert_eq!(
+            samples.iter().map(|s| s.decode_time).collect::<Vec<_>>(),
+            vec![0, 40, 80]
+        );
+        assert_eq!(
+            samples.iter().map(|s| s.is_keyframe).collect::<Vec<_>>(),
+            vec![true, false, false]
+        );
+        assert!(samples.iter().all(|s| s.size == 100 && s.duration == 40));
+    }
+
+    #[test]
+    fn parse_moof_uses_the_moof_as_default_base() {
+        let mut trun = 2u32.to_be_bytes().to_vec();
+        trun.extend(16i32.to_be_bytes()); // data_offset
+        [10u32, 20]
+            .iter()
+            .for_each(|s| trun.extend(s.to_be_bytes()));
+
+        let traf = [
+            full_box(b"tfhd", 0, 0x20000, &1u32.to_be_bytes()),
+            full_box(b"trun", 0, 0x1 | 0x200, &trun),
+        ]
+        .concat();
+        let samples = parse_test_moof(&mp4_box(b"traf", &traf), 1000, true);
+
+        assert_eq!(
+            samples
+                .iter()
+                .map(|s| (s.offset, s.size))
+                .collect::<Vec<_>>(),
+            vec![(1016, 10), (1026, 20)]
+        );
+
+        // Without a data offset, samples start at the base.
+        let traf = [
+            full_box(b"tfhd", 0, 0x20000, &1u32.to_be_bytes()),
+            full_box(
+                b"trun",
+                0,
+                0x200,
+                &[1u32.to_be_bytes(), 10u32.to_be_bytes()].concat(),
+            ),
+        ]
+        .concat();
+        let samples = parse_test_moof(&mp4_box(b"traf", &traf), 1000, true);
+
+        assert_eq!(samples[0].offset, 1000);
+    }
+
+    #[test]
+    fn parse_moof_only_reads_the_non_sync_flag_of_video() {
+        let mut trun = 2u32.to_be_bytes().to_vec();
+        trun.extend(SYNC_SAMPLE.to_be_bytes());
+        trun.extend(NON_SYNC_SAMPLE.to_be_bytes());
+
+        let traf = [
+            full_box(b"tfhd", 0, 0x20000, &1u32.to_be_bytes()),
+            full_box(b"trun", 0, 0x400, &trun),
+        ]
+        .concat();
+
+        let video = parse_test_moof(&mp4_box(b"traf", &traf), 0, true);
+        let audio = parse_test_moof(&mp4_box(b"traf", &traf), 0, false);
+
+        assert_eq!(
+            video.iter().map(|s| s.is_keyframe).collect::<Vec<_>>(),
+            vec![true, false]
+        );
+        assert!(audio.iter().all(|s| s.is_keyframe));
+    }
+
+    #[test]
+    fn parse_moof_reads_signed_composition_offsets_of_version_1() {
+        let mut trun = 1u32.to_be_bytes().to_vec();
+        trun.extend((-40i32).to_be_bytes());
+
+        let tfhd = full_box(b"tfhd", 0, 0x20000, &1u32.to_be_bytes());
+        let version_0 = [tfhd.clone(), full_box(b"trun", 0, 0x800, &trun)].concat();
+        let version_1 = [tfhd, full_box(b"trun", 1, 0x800, &trun)].concat();
+
+        assert_eq!(
+            parse_test_moof(&mp4_box(b"traf", &version_0), 0, true)[0].composition_offset,
+            u32::MAX as i64 - 39
+        );
+        assert_eq!(
+            parse_test_moof(&mp4_box(b"traf", &version_1), 0, true)[0].composition_offset,
+            -40
+        );
+    }
+
+    #[test]
+    fn parse_moof_continues_decode_time_without_tfdt() {
+        let traf = [
+            full_box(
+                b"tfhd",
+                0,
+                0x20000 | 0x8,
+                &[1u32, 40].map(u32::to_be_bytes).concat(),
+            ),
+            full_box(b"trun", 0, 0, &2u32.to_be_bytes()),
+        ]
+        .concat();
+        let moof = mp4_box(b"traf", &traf);
+
+        let mut tracks = vec![new_track(true)];
+        let mut next_decode_time = HashMap::new();
+        for _ in 0..2 {
+            parse_moof(
+                &moof,
+                0,
+                &mut tracks,
+                &HashMap::new(),
+                &mut next_decode_time,
+            )
+            .unwrap();
+        }
+
+        assert_eq!(
+            tracks[0]
+                .samples
+                .iter()
+                .map(|s| s.decode_time)
+                .collect::<Vec<_>>(),
+            vec![0, 40, 80, 120]
+        );
+    }
+
+    #[test]
+    fn parse_elst_skips_empty_edits() {
+        let mut version_0 = 2u32.to_be_bytes().to_vec();
+        version_0.extend([0, 0, 0, 100]); // segment_duration
+        version_0.extend((-1i32).to_be_bytes()); // empty edit
+        version_0.extend(0x10000u32.to_be_bytes());
+        version_0.extend([0, 0, 0, 100]);
+        version_0.extend(1024i32.to_be_bytes());
+        version_0.extend(0x10000u32.to_be_bytes());
+
+        let mut version_1 = 1u32.to_be_bytes().to_vec();
+        version_1.extend(100u64.to_be_bytes());
+        version_1.extend(3_000_000_000u64.to_be_bytes());
+        version_1.extend(0x10000u32.to_be_bytes());
+
+        assert_eq!(
+            parse_elst(&[&[0; 4][..], &version_0].concat()).unwrap(),
+            1024
+        );
+        assert_eq!(
+            parse_elst(&[&[1, 0, 0, 0][..], &version_1].concat()).unwrap(),
+            3_000_000_000
+        );
+        assert_eq!(parse_elst(&[0; 8]).unwrap(), 0);
+        assert!(parse_elst(&[0, 0, 0, 0, 0, 0, 0, 1]).is_err());
+    }
+
+    #[test]
+    fn read_box_header_rejects_sizes_past_the_end_of_the_file() {
+        let mut data = vec![0xFF, 0xFF, 0xFF, 0xF0];
+        data.extend_from_slice(b"moov");
+        data.extend_from_slice(&[0; 8]);
+
+        let error = read_box_header(&mut Cursor::new(&data), data.len() as u64).unwrap_err();
+
+        assert!(matches!(error, Error::InputError(_)));
+    }
+
+    #[test]
+    fn read_box_header_rejects_overflowing_large_sizes() {
+        let mut data = vec![0, 0, 0, 1];
+        data.extend_from_slice(b"mdat");
+        data.extend_from_slice(&u64::MAX.to_be_bytes());
+
+        let error = read_box_header(&mut Cursor::new(&data), data.len() as u64).unwrap_err();
+
+        assert!(matches!(error, Error::InputError(_)));
+    }
+
+    #[test]
+    fn read_box_header_accepts_boxes_that_fit() {
+        let mut data = vec![0, 0, 0, 12];
+        data.extend_from_slice(b"free");
+        data.extend_from_slice(&[0; 4]);
+
+        let header = read_box_header(&mut Cursor::new(&data), data.len() as u64)
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(&header.kind, b"free");
+        assert_eq!(header.end, 12);
+        assert!(read_box_header(&mut Cursor::new(&data[..4]), 4)
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn children_stops_at_truncated_boxes() {
+        let mut data = vec![0, 0, 0, 9];
+        data.extend_from_slice(b"free");
+        data.push(0);
+        data.extend_from_slice(&[0, 0, 0, 1]);
+        data.extend_from_slice(b"mdat");
+        data.extend_from_slice(&u64::MAX.to_be_bytes());
+
+        let kinds: Vec<_> = children(&data).map(|(k, _)| k).collect();
+
+        assert_eq!(kinds, vec![*b"free"]);
+    }
+}
@@ -0,0 +1,774 @@
+use crate::{
+    combiner::SubtitleTrack,
+    error::{Error, Result},
+    font::get_font_media_type,
+    matroska::write_matroska,
+    subtitle::{get_iso_639_2_language, SubtitleType},
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    ffi::OsStr,
+    fs,
+    io::{self, BufRead, BufReader, Read},
+    path::{self, Path, PathBuf},
+    process::{Command, Stdio},
+    thread,
+    time::Duration,
+};
+
+/// Everything needed to combine the audio, video, and subtitles of an episode.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+pub struct MuxJob {
+    pub video_path: PathBuf,
+    pub audio_path: PathBuf,
+    pub subtitle_tracks: Vec<SubtitleTrack>,
+    /// Index of the subtitle track marked as default, or burned for hard subtitles. No track is
+    /// marked as default if it's out of range.
+    pub default_track: usize,
+    pub subtitle_type: SubtitleType,
+    /// Title of the output file.
+    pub title: String,
+    pub output_path: PathBuf,
+    /// Length of the episode, used to calculate the progress.
+    pub duration: Option<Duration>,
+    /// Files attached to the output, e.g. fonts used by soft subtitles.
+    pub attachments: Vec<PathBuf>,
+}
+
+/// Progress of a running `Muxer`.
+///
+/// ```
+/// use bilibili_extractor_lib::muxer::MuxProgress;
+/// use std::time::Duration;
+///
+/// let progress = MuxProgress {
+///     out_time: Duration::from_secs(30),
+///     duration: Some(Duration::from_secs(120)),
+///     speed: Some(2.),
+///     is_finished: false,
+/// };
+///
+/// assert_eq!(progress.get_percentage(), Some(25.));
+/// assert_eq!(progress.get_eta(), Some(Duration::from_secs(45)));
+/// ```
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, PartialOrd)]
+pub struct MuxProgress {
+    /// How much of the episode has been written.
+    pub out_time: Duration,
+    /// Length of the episode, if known.
+    pub duration: Option<Duration>,
+    /// Processing speed relative to playback, e.g. `2.0` is twice as fast as playback.
+    pub speed: Option<f64>,
+    pub is_finished: bool,
+}
+
+impl MuxProgress {
+    /// Get the progress in percent. Returns `None` if the length of the episode is unknown.
+    pub fn get_percentage(&self) -> Option<f64> {
+        match self.is_finished {
+            true => Some(100.),
+            false => self
+                .duration
+                .filter(|d| !d.is_zero())
+                .map(|d| (self.out_time.as_secs_f64() / d.as_secs_f64() * 100.).min(100.)),
+        }
+    }
+
+    /// Get the estimated time left. Returns `None` if the length of the episode or the speed is
+    /// unknown.
+    pub fn get_eta(&self) -> Option<Duration> {
+        let duration = self.duration?;
+        let speed = self.speed.filter(|s| *s > 0.)?;
+
+        Some(Duration::from_secs_f64(
+            duration.saturating_sub(self.out_time).as_secs_f64() / speed,
+        ))
+    }
+}
+
+/// A backend that combines the audio, video, and subtitles of an episode into a single file.
+///
+/// # Use a custom muxer
+///
+/// ```
+/// use bilibili_extractor_lib::{
+///     error::Result,
+///     muxer::{MuxJob, Muxer},
+/// };
+/// use std::sync::Mutex;
+///
+/// #[derive(Default)]
+/// struct FakeMuxer {
+///     jobs: Mutex<Vec<MuxJob>>,
+/// }
+///
+/// impl Muxer for FakeMuxer {
+///     fn mux(&self, job: &MuxJob) -> Result<()> {
+///         self.jobs.lock().unwrap().push(job.clone());
+///
+///         Ok(())
+///     }
+/// }
+///
+/// let muxer = FakeMuxer::default();
+/// muxer.mux(&MuxJob {
+///     video_path: "video.m4s".into(),
+///     audio_path: "audio.m4s".into(),
+///     subtitle_tracks: vec![],
+///     default_track: 0,
+///     subtitle_type: Default::default(),
+///     title: "EP01".into(),
+///     output_path: "episode.mkv".into(),
+///     duration: None,
+///     attachments: vec![],
+/// })
+/// .unwrap();
+///
+/// assert_eq!(muxer.jobs.lock().unwrap().len(), 1);
+/// ```
+pub trait Muxer: Send + Sync {
+    /// Combine everything in the job into `job.output_path`.
+    fn mux(&self, job: &MuxJob) -> Result<()>;
+
+    /// Same as `mux`, but calls `on_progress` while muxing. Muxers that can't report progress
+    /// only call it once they're finished.
+    fn mux_with_progress(&self, job: &MuxJob, on_progress: &dyn Fn(&MuxProgress)) -> Result<()> {
+        self.mux(job)?;
+
+        on_progress(&MuxProgress {
+            out_time: job.duration.unwrap_or_default(),
+            duration: job.duration,
+            speed: None,
+            is_finished: true,
+        });
+
+        Ok(())
+    }
+
+    /// Join media segments in order into `output_path`, e.g. the FLV segments of older
+    /// downloads. Muxers that can't join segments return an error.
+    fn concat(&self, _segments: &[PathBuf], _output_path: &Path) -> Result<()> {
+        Err("This muxer can't join segmented downloads.".into())
+    }
+}
+
+/// Muxer that uses `ffmpeg`. Returns `Error::FFmpegError` if `ffmpeg` exits with a non-zero
+/// status.
+///
+/// # Add extra arguments
+///
+/// ```
+/// use bilibili_extractor_lib::muxer::FFmpegMuxer;
+///
+/// let muxer = FFmpegMuxer::new("/usr/local/bin/ffmpeg")
+///     .with_input_args(["-fflags", "+genpts"])
+///     .with_output_args(["-max_interleave_delta", "0"]);
+///
+/// assert_eq!(muxer.output_args, ["-max_interleave_delta", "0"]);
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(default)]
+pub struct FFmpegMuxer {
+    /// Path of the `ffmpeg` executable. Looked up in `PATH` if it's only a name.
+    pub ffmpeg_path: PathBuf,
+    /// Extra arguments placed before every input, e.g. `-thread_queue_size 1024`, so they apply
+    /// to the audio and subtitles as well as the video.
+    pub input_args: Vec<String>,
+    /// Extra arguments placed right before the output file.
+    pub output_args: Vec<String>,
+    /// Encoder used when burning hard subtitles.
+    pub encoder: EncoderConfig,
+}
+
+/// Encoder settings used when burning hard subtitles, since the video has to be re-encoded.
+/// Options left as `None` use the defaults of the encoder.
+///
+/// ```
+/// use bilibili_extractor_lib::muxer::{EncoderConfig, FFmpegMuxer, VideoCodec, VideoQuality};
+///
+/// let muxer = FFmpegMuxer {
+///     encoder: EncoderConfig {
+///         video_codec: VideoCodec::X265,
+///         quality: Some(VideoQuality::Crf(22)),
+///         preset: Some("slow".into()),
+///         pixel_format: Some("yuv420p10le".into()),
+///         ..Default::default()
+///     },
+///     ..Default::default()
+/// };
+///
+/// assert_eq!(muxer.encoder.get_args()[..2], ["-c:v", "libx265"]);
+/// ```
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(default)]
+pub struct EncoderConfig {
+    pub video_codec: VideoCodec,
+    pub quality: Option<VideoQuality>,
+    /// Encoder preset, e.g. `slow` for x264 and x265, or `6` for AV1.
+    pub preset: Option<String>,
+    /// Pixel format of the output, e.g. `yuv420p`.
+    pub pixel_format: Option<String>,
+    /// Codec used to re-encode the audio, e.g. `aac`. The audio is copied if it's `None`.
+    pub audio_codec: Option<String>,
+    /// Bitrate of the re-encoded audio, e.g. `192k`.
+    pub audio_bitrate: Option<String>,
+}
+
+/// Software video encoders that can be used to burn hard subtitles.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub enum VideoCodec {
+    #[default]
+    X264,
+    X265,
+    Av1,
+}
+
+/// Rate control of the video encoder.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+pub enum VideoQuality {
+    /// Constant rate factor. Lower is better.
+    Crf(u8),
+    /// Target bitrate, e.g. `4M`.
+    Bitrate(String),
+}
+
+impl VideoCodec {
+    /// Get the name of the `ffmpeg` encoder.
+    pub fn get_encoder_name(&self) -> &'static str {
+        match self {
+            Self::X264 => "libx264",
+            Self::X265 => "libx265",
+            Self::Av1 => "libsvtav1",
+        }
+    }
+}
+
+impl EncoderConfig {
+    /// Get the `ffmpeg` arguments for the encoders.
+    pub fn get_args(&self) -> Vec<String> {
+        let mut args = vec!["-c:v".into(), self.video_codec.get_encoder_name().into()];
+
+        match &self.quality {
+            Some(VideoQuality::Crf(crf)) => args.extend(["-crf".into(), crf.to_string()]),
+            Some(VideoQuality::Bitrate(bitrate)) => args.extend(["-b:v".into(), bitrate.clone()]),
+            None => {}
+        }
+        if let Some(preset) = &self.preset {
+            args.extend(["-preset".into(), preset.clone()]);
+        }
+        if let Some(pixel_format) = &self.pixel_format {
+            args.extend(["-pix_fmt".into(), pixel_format.clone()]);
+        }
+
+        match &self.audio_codec {
+            Some(audio_codec) => {
+                args.extend(["-c:a".into(), audio_codec.clone()]);
+
+                if let Some(audio_bitrate) = &self.audio_bitrate {
+                    args.extend(["-b:a".into(), audio_bitrate.clone()]);
+                }
+            }
+            None => args.extend(["-c:a".into(), "copy".into()]),
+        }
+
+        args
+    }
+}
+
+impl FFmpegMuxer {
+    /// Create a new `FFmpegMuxer` that uses the given `ffmpeg` executable.
+    pub fn new(ffmpeg_path: impl Into<PathBuf>) -> Self {
+        Self {
+            ffmpeg_path: ffmpeg_path.into(),
+            input_args: vec![],
+            output_args: vec![],
+            encoder: Default::default(),
+        }
+    }
+
+    /// Append arguments placed before every input.
+    pub fn with_input_args(mut self, args: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.input_args.extend(args.into_iter().map(Into::into));
+        self
+    }
+
+    /// Append arguments placed right before the output file.
+    pub fn with_output_args(mut self, args: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.output_args.extend(args.into_iter().map(Into::into));
+        self
+    }
+
+    /// Build the `ffmpeg` command of a job.
+    fn get_mux_command(&self, job: &MuxJob) -> Result<Command> {
+        let mut binding = Command::new(&self.ffmpeg_path);
+        binding
+            .arg("-y")
+            .args(["-hide_banner", "-loglevel", "error"]) // silent operation
+            .args(["-nostats", "-progress", "pipe:1"])
+            .args(&self.input_args)
+            .args([OsStr::new("-i"), job.video_path.as_os_str()])
+            .args(&self.input_args)
+            .args([OsStr::new("-i"), job.audio_path.as_os_str()]);
+
+        match job.subtitle_type {
+            SubtitleType::Hard => {
+                let subtitle_track = job
+                    .subtitle_tracks
+                    .get(job.default_track)
+                    .ok_or("Default subtitle track doesn't exist.")?;
+
+                binding
+                    .args([
+                        "-vf",
+                        &format!(
+                            "subtitles=filename={}",
+                            escape_filter_value(&subtitle_track.path.to_string_lossy())
+                        ),
+                    ])
+                    .args(self.encoder.get_args())
+            }
+            SubtitleType::Soft => {
+                job.subtitle_tracks.iter().for_each(|t| {
+                    binding
+                        .args(&self.input_args)
+                        .args([OsStr::new("-i"), t.path.as_os_str()]);
+                });
+
+                binding.args(["-map", "0:v"]).args(["-map", "1:a:0"]);
+
+                job.subtitle_tracks.iter().enumerate().for_each(|(i, t)| {
+                    binding
+                        .args(["-map", &(i + 2).to_string()])
+                        .args([
+                            &format!("-metadata:s:s:{i}"),
+                            &format!("language={}", get_iso_639_2_language(&t.language)),
+                        ])
+                        .args([
+                            &format!("-metadata:s:s:{i}"),
+                            &format!("title={}", t.get_title()),
+                        ])
+                        .arg(format!("-disposition:s:s:{i}"))
+                        .arg(match i == job.default_track {
+                            true => "default",
+                            false => "0",
+                        });
+                });
+
+                job.attachments.iter().enumerate().for_each(|(i, a)| {
+                    binding.args([OsStr::new("-attach"), a.as_os_str()]).args([
+                        &format!("-metadata:s:t:{i}"),
+                        &format!("mimetype={}", get_font_media_type(a)),
+                    ]);
+                });
+
+                binding.args(["-codec", "copy"])
+            }
+        };
+
+        binding
+            .args(["-metadata", &format!("title={}", job.title)])
+            .args(&self.output_args)
+            .arg(&job.output_path);
+
+        Ok(binding)
+    }
+}
+
+impl Default for FFmpegMuxer {
+    fn default() -> Self {
+        Self::new("ffmpeg")
+    }
+}
+
+impl Muxer for FFmpegMuxer {
+    fn mux(&self, job: &MuxJob) -> Result<()> {
+        self.mux_with_progress(job, &|_| {})
+    }
+
+    fn mux_with_progress(&self, job: &MuxJob, on_progress: &dyn Fn(&MuxProgress)) -> Result<()> {
+        run_ffmpeg(&mut self.get_mux_command(job)?, job.duration, on_progress)
+    }
+
+    /// Joins the segments with the `concat` demuxer, without re-encoding.
+    fn concat(&self, segments: &[PathBuf], output_path: &Path) -> Result<()> {
+        let list_path = output_path.with_extension("txt");
+        let list = segments
+            .iter()
+            .map(|s| {
+                Ok(format!(
+                    "file '{}'\n",
+                    path::absolute(s)?.to_string_lossy().replace('\'', r"'\''")
+                ))
+            })
+            .collect::<Result<String>>()?;
+        fs::write(&list_path, list)?;
+
+        let mut binding = Command::new(&self.ffmpeg_path);
+        binding
+            .arg("-y")
+            .args(["-hide_banner", "-loglevel", "error"]) // silent operation
+            .args(["-nostats", "-progress", "pipe:1"])
+            .args(&self.input_args)
+            .args(["-f", "concat", "-safe", "0"])
+            .args([OsStr::new("-i"), list_path.as_os_str()])
+            .args(["-codec", "copy"])
+            .arg(output_path);
+
+        let result = run_ffmpeg(&mut binding, None, &|_| {});
+        let _ = fs::remove_file(list_path);
+
+        result
+    }
+}
+
+/// Muxer that writes Matroska files directly, without `ffmpeg`. Only soft subtitles are
+/// supported, since burning subtitles needs re-encoding.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+pub struct NativeMuxer;
+
+impl Muxer for NativeMuxer {
+    fn mux(&self, job: &MuxJob) -> Result<()> {
+        if job.subtitle_type == SubtitleType::Hard {
+            return Err("The native muxer doesn't support hard subtitles.".into());
+        }
+
+        write_matroska(job)
+    }
+}
+
+/// Escape a value so that it can be used as a filter option in an `ffmpeg` filtergraph, e.g. the
+/// path in `subtitles=filename=<path>`. The value is escaped for the option parser first, then
+/// for the filtergraph parser.
+///
+/// ```
+/// use bilibili_extractor_lib::muxer::escape_filter_value;
+///
+/// assert_eq!(
+///     escape_filter_value("/anime/Re:Zero/subtitle.en.ass"),
+///     r"/anime/Re\\:Zero/subtitle.en.ass"
+/// );
+/// assert_eq!(
+///     escape_filter_value("/anime/JoJo's Bizarre Adventure/subtitle.en.ass"),
+///     r"/anime/JoJo\\\'s Bizarre Adventure/subtitle.en.ass"
+/// );
+/// assert_eq!(
+///     escape_filter_value("/anime/[Oshi no Ko], Part 2; Finale/subtitle.en.ass"),
+///     r"/anime/\[Oshi no Ko\]\, Part 2\; Finale/subtitle.en.ass"
+/// );
+/// assert_eq!(
+///     escape_filter_value(r"C:\Anime\Fate=Zero\subtitle.en.ass"),
+///     r"C\\:\\\\Anime\\\\Fate=Zero\\\\subtitle.en.ass"
+/// );
+/// ```
+pub fn escape_filter_value(value: &str) -> String {
+    escape(
+        &escape(value, &['\\', '\'', ':']),
+        &['\\', '\'', '[', ']', ',', ';'],
+    )
+}
+
+/// Put a backslash before every special character.
+fn escape(value: &str, special_characters: &[char]) -> String {
+    value.chars().fold(String::new(), |mut escaped, c| {
+        if special_characters.contains(&c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+        escaped
+    })
+}
+
+/// Run an `ffmpeg` command, turning a non-zero exit status into `Error::FFmpegError`. The
+/// `-progress` output of `ffmpeg` is read from its stdout and passed to `on_progress`.
+fn run_ffmpeg(
+    command: &mut Command,
+    duration: Option<Duration>,
+    on_progress: &dyn Fn(&MuxProgress),
+) -> Result<()> {
+    let mut child = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    // Read stderr on another thread so that a full pipe can't block `ffmpeg`.
+    let stderr_reader = child.stderr.take().map(|mut stderr| {
+        thread::spawn(move || {
+            let mut buffer = String::new();
+            let _ = stderr.read_to_string(&mut buffer);
+            buffer
+        })
+    });
+
+    let read_result = match child.stdout.take() {
+        Some(stdout) => read_progress(stdout, duration, on_progress),
+        None => Ok(()),
+    };
+
+    // `ffmpeg` is stopped if its progress can't be read, and waited for either way so it doesn't
+    // become a zombie. Its stderr closes when it exits, which ends the reader thread.
+    if read_result.is_err() {
+        let _ = child.kill();
+    }
+    let status = child.wait();
+    let stderr = stderr_reader
+        .and_then(|r| r.join().ok())
+        .unwrap_or_default();
+
+    read_result?;
+    let status = status?;
+
+    if status.success() {
+        return Ok(());
+    }
+
+    Err(Error::FFmpegError {
+        code: status.code(),
+        command: command_to_string(command),
+        stderr: stderr.trim().into(),
+    })
+}
+
+/// Read the `-progress` output of `ffmpeg` until it ends, calling `on_progress` for every block.
+fn read_progress(
+    stdout: impl Read,
+    duration: Option<Duration>,
+    on_progress: &dyn Fn(&MuxProgress),
+) -> io::Result<()> {
+    let mut progress = MuxProgress {
+        duration,
+        ..Default::default()
+    };
+
+    for line in BufReader::new(stdout).lines() {
+        let line = line?;
+        let Some((key, value)) = line.split_once('=') else {
+            continue;
+        };
+
+        match key.trim() {
+            // `out_time_ms` is in microseconds as well.
+            "out_time_us" | "out_time_ms" => {
+                if let Ok(t) = value.trim().parse::<u64>() {
+                    progress.out_time = Duration::from_micros(t);
+                }
+            }
+            "speed" => progress.speed = value.trim().trim_end_matches('x').parse().ok(),
+            "progress" => {
+                progress.is_finished = value.trim() == "end";
+                on_progress(&progress);
+            }
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+/// Format a command the way it would be typed in a shell.
+fn command_to_string(command: &Command) -> String {
+    std::iter::once(command.get_program())
+        .chain(command.get_args())
+        .map(|a| {
+            let a = a.to_string_lossy();
+
+            match a.contains(|c: char| c.is_whitespace() || "'\"\\$`;&|<>()*?[]".contains(c)) {
+                true => format!("'{}'", a.replace('\'', "'\\''")),
+                false => a.into_owned(),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Build the `ffmpeg` arguments of a job whose subtitle is inside `directory`.
+    fn get_args(directory: &str, subtitle_type: SubtitleType) -> Vec<String> {
+        let job = MuxJob {
+            video_path: format!("{directory}/64/video.m4s").into(),
+            audio_path: format!("{directory}/64/audio.m4s").into(),
+            subtitle_tracks: vec![SubtitleTrack {
+                path: format!("{directory}/subtitle.en.ass").into(),
+                language: "en".into(),
+                title: None,
+                keep_fonts: false,
+            }],
+            default_track: 0,
+            subtitle_type,
+            title: "EP01 - Re:Start, [Part 1]; It's \\ Over".into(),
+            output_path: format!("{directory}/64/episode.mkv").into(),
+            duration: None,
+            attachments: vec![],
+        };
+
+        FFmpegMuxer::new("ffmpeg")
+            .get_mux_command(&job)
+            .unwrap()
+            .get_args()
+            .map(|a| a.to_string_lossy().into_owned())
+            .collect()
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn run_ffmpeg_stops_ffmpeg_if_progress_is_unreadable() {
+        let pid_path = crate::mp4::tests::get_temporary_path("ffmpeg.pid");
+        // Invalid UTF-8 can't be read as a line of progress.
+        let mut command = Command::new("sh");
+        command.arg("-c").arg(format!(
+            "echo $$ > '{}'; printf 'progress=continue\\n\\377\\n'; exec sleep 30",
+            pid_path.display()
+        ));
+        let progress_count = std::sync::atomic::AtomicUsize::new(0);
+
+        let result = run_ffmpeg(&mut command, None, &|_| {
+            progress_count.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        });
+        let pid = fs::read_to_string(&pid_path).unwrap();
+        fs::remove_file(&pid_path).unwrap();
+
+        assert!(matches!(result, Err(Error::IOError(_))));
+        assert_eq!(progress_count.into_inner(), 1);
+        // The process is gone, so it was killed and waited for.
+        assert!(!Path::new("/proc").join(pid.trim()).exists());
+    }
+
+    #[test]
+    fn input_args_are_placed_before_every_input() {
+        let job = MuxJob {
+            video_path: "video.m4s".into(),
+            audio_path: "audio.m4s".into(),
+            subtitle_tracks: vec![SubtitleTrack {
+                path: "subtitle.en.ass".into(),
+                language: "en".into(),
+                title: None,
+                keep_fonts: false,
+            }],
+            default_track: 0,
+            subtitle_type: SubtitleType::Soft,
+            title: "EP01".into(),
+            output_path: "episode.mkv".into(),
+            duration: None,
+            attachments: vec![],
+        };
+        let args: Vec<_> = FFmpegMuxer::new("ffmpeg")
+            .with_input_args(["-thread_queue_size", "1024"])
+            .get_mux_command(&job)
+            .unwrap()
+            .get_args()
+            .map(|a| a.to_string_lossy().into_owned())
+            .collect();
+
+        let inputs: Vec<_> = args
+            .windows(4)
+            .filter(|w| w[2] == "-i")
+            .map(|w| (w[0].as_str(), w[1].as_str(), w[3].as_str()))
+            .collect();
+        assert_eq!(
+            inputs,
+            [
+                ("-thread_queue_size", "1024", "video.m4s"),
+                ("-thread_queue_size", "1024", "audio.m4s"),
+                ("-thread_queue_size", "1024", "subtitle.en.ass"),
+            ]
+        );
+    }
+
+    /// Get the filtergraph passed to `-vf` for a subtitle inside `directory`.
+    fn get_filter(directory: &str) -> String {
+        let args = get_args(directory, SubtitleType::Hard);
+        let position = args.iter().position(|a| a == "-vf").unwrap();
+
+        args[position + 1].clone()
+    }
+
+    #[test]
+    fn filter_escapes_colons() {
+        assert_eq!(
+            get_filter("/anime/Re:Zero"),
+            r"subtitles=filename=/anime/Re\\:Zero/subtitle.en.ass"
+        );
+    }
+
+    #[test]
+    fn filter_escapes_quotes() {
+        assert_eq!(
+            get_filter("/anime/JoJo's Bizarre Adventure"),
+            r"subtitles=filename=/anime/JoJo\\\'s Bizarre Adventure/subtitle.en.ass"
+        );
+    }
+
+    #[test]
+    fn filter_escapes_commas() {
+        assert_eq!(
+            get_filter("/anime/Yes, No"),
+            r"subtitles=filename=/anime/Yes\, No/subtitle.en.ass"
+        );
+    }
+
+    #[test]
+    fn filter_escapes_brackets() {
+        assert_eq!(
+            get_filter("/anime/[Oshi no Ko]"),
+            r"subtitles=filename=/anime/\[Oshi no Ko\]/subtitle.en.ass"
+        );
+    }
+
+    #[test]
+    fn filter_escapes_backslashes() {
+        assert_eq!(
+            get_filter(r"C:\Anime\Fate"),
+            r"subtitles=filename=C\\:\\\\Anime\\\\Fate/subtitle.en.ass"
+        );
+    }
+
+    #[test]
+    fn filter_escapes_semicolons() {
+        assert_eq!(
+            get_filter("/anime/Steins;Gate"),
+            r"subtitles=filename=/anime/Steins\;Gate/subtitle.en.ass"
+        );
+    }
+
+    #[test]
+    fn filter_escapes_every_special_character_together() {
+        assert_eq!(
+            get_filter(r"/anime/Re:Zero, [Part 1]; It's \ Over"),
+            r"subtitles=filename=/anime/Re\\:Zero\, \[Part 1\]\; It\\\'s \\\\ Over/subtitle.en.ass"
+        );
+    }
+
+    #[test]
+    fn filter_keeps_ordinary_characters() {
+        assert_eq!(
+            get_filter("/anime/Frieren = 葬送のフリーレン (2023)"),
+            "subtitles=filename=/anime/Frieren = 葬送のフリーレン (2023)/subtitle.en.ass"
+        );
+    }
+
+    #[test]
+    fn hostile_paths_and_titles_are_passed_unescaped_outside_the_filter() {
+        let directory = r"/anime/Re:Zero, [Part 1]; It's \ Over";
+
+        for subtitle_type in [SubtitleType::Hard, SubtitleType::Soft] {
+            let args = get_args(directory, subtitle_type);
+
+            assert!(args.contains(&format!("{directory}/64/video.m4s")));
+            assert!(args.contains(&format!("{directory}/64/audio.m4s")));
+            assert!(args.contains(&"title=EP01 - Re:Start, [Part 1]; It's \\ Over".to_string()));
+            assert_eq!(args.last(), Some(&format!("{directory}/64/episode.mkv")));
+        }
+
+        let args = get_args(directory, SubtitleType::Soft);
+        assert!(args.contains(&format!("{directory}/subtitle.en.ass")));
+        // Output options must follow the last input.
+        assert!(
+            args.iter().rposition(|a| a == "-i")
+                < args.iter().position(|a| a.starts_with("title=EP01"))
+        );
+        assert!(!args.contains(&"-vf".to_string()));
+    }
+}
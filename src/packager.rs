@@ -1,6 +1,7 @@
 use crate::{
-    error::Result,
-    metadata::{EpisodeId, EpisodeMetadata, SeasonMetadata},
+    error::{Error, Result},
+    event::EventHandler,
+    metadata::{EpisodeMetadata, SeasonMetadata},
 };
 use serde::{Deserialize, Serialize};
 use std::{
@@ -9,64 +10,10 @@ use std::{
 };
 
 macro_rules! package_episode {
-    ($package_method: ident, $episode_ident: ident, $name_format: literal, $episode_metadata: expr, $episode_video_path: expr, $packager: expr) => {
+    ($package_method: ident, $episode_metadata: expr, $episode_video_path: expr, $packager: expr) => {
         $package_method(
             $episode_video_path,
-            $packager
-                .output_path
-                .join(&$episode_metadata.title)
-                .join(format!(
-                    $name_format,
-                    $episode_metadata.title, $episode_metadata.$episode_ident
-                )),
-        )?
-    };
-}
-
-macro_rules! package_epidode_by_copying {
-    (normal, $episode_metadata: expr, $episode_video_path: expr, $packager: expr) => {
-        package_episode!(
-            copy,
-            episode,
-            "{} EP{}.mkv",
-            $episode_metadata,
-            $episode_video_path,
-            $packager
-        )
-    };
-
-    (special, $episode_metadata: expr, $episode_video_path: expr, $packager: expr) => {
-        package_episode!(
-            copy,
-            episode,
-            "{} {}.mkv",
-            $episode_metadata,
-            $episode_video_path,
-            $packager
-        )
-    };
-}
-
-macro_rules! package_epidode_by_moving {
-    (normal, $episode_metadata: expr, $episode_video_path: expr, $packager: expr) => {
-        package_episode!(
-            rename,
-            episode,
-            "{} EP{}.mkv",
-            $episode_metadata,
-            $episode_video_path,
-            $packager
-        )
-    };
-
-    (special, $episode_metadata: expr, $episode_video_path: expr, $packager: expr) => {
-        package_episode!(
-            rename,
-            episode,
-            "{} {}.mkv",
-            $episode_metadata,
-            $episode_video_path,
-            $packager
+            $packager.get_episode_package_path($episode_metadata),
         )
     };
 }
@@ -113,39 +60,121 @@ impl Packager {
 
     /// Package a season.
     pub fn save_season(&self, season_metadata: &SeasonMetadata) -> Result<()> {
+        self.save_season_with_events(season_metadata, &())
+    }
+
+    /// Package a season, sending events to `events`.
+    pub fn save_season_with_events(
+        &self,
+        season_metadata: &SeasonMetadata,
+        events: &dyn EventHandler,
+    ) -> Result<()> {
+        events.on_season_started(season_metadata);
+
         season_metadata
             .episodes
             .iter()
-            .try_for_each(|e| self.save_episode(e))?;
+            .try_for_each(|e| self.save_episode_with_events(e, events))?;
 
         Ok(())
     }
 
+    /// Package episode, sending events to `events`.
+    pub fn save_episode_with_events(
+        &self,
+        episode_metadata: &EpisodeMetadata,
+        events: &dyn EventHandler,
+    ) -> Result<()> {
+        match self.save_episode(episode_metadata) {
+            Ok(_) => {
+                events.on_file_packaged(
+                    episode_metadata,
+                    &self.get_episode_package_path(episode_metadata),
+                );
+
+                Ok(())
+            }
+            Err(e) => {
+                events.on_error(Some(episode_metadata), &e);
+
+                Err(e)
+            }
+        }
+    }
+
     /// Package episode.
     pub fn save_episode(&self, episode_metadata: &EpisodeMetadata) -> Result<()> {
         let episode_video_path = get_episode_video_path!(episode, episode_metadata);
-        create_dir_all(self.output_path.join(&episode_metadata.title))?;
 
-        if self.config.copy {
-            match episode_metadata.episode {
-                EpisodeId::Normal(_) => {
-                    package_epidode_by_copying!(normal, episode_metadata, episode_video_path, self)
-                }
-                EpisodeId::Special(_) => {
-                    package_epidode_by_copying!(special, episode_metadata, episode_video_path, self)
-                }
-            };
-        } else {
-            match episode_metadata.episode {
-                EpisodeId::Normal(_) => {
-                    package_epidode_by_moving!(normal, episode_metadata, episode_video_path, self)
-                }
-                EpisodeId::Special(_) => {
-                    package_epidode_by_moving!(special, episode_metadata, episode_video_path, self)
+        create_dir_all(self.get_season_package_path(episode_metadata))
+            .and_then(|_| match self.config.copy {
+                true => {
+                    package_episode!(copy, episode_metadata, &episode_video_path, self).map(|_| ())
                 }
-            };
-        }
+                false => package_episode!(rename, episode_metadata, &episode_video_path, self),
+            })
+            .map_err(|e| {
+                Error::PackagingError(format!(
+                    "Cannot package {}: {e}",
+                    episode_video_path.display()
+                ))
+            })
+    }
 
-        Ok(())
+    /// Get the directory the episodes of a season are packaged to.
+    pub fn get_season_package_path(&self, episode_metadata: &EpisodeMetadata) -> PathBuf {
+        self.output_path
+            .join(sanitize_file_name(&episode_metadata.title))
+    }
+
+    /// Get where the episode is packaged to.
+    pub fn get_episode_package_path(&self, episode_metadata: &EpisodeMetadata) -> PathBuf {
+        self.get_season_package_path(episode_metadata).join(format!(
+            "{}.mkv",
+            sanitize_file_name(&format!(
+                "{} {}",
+                episode_metadata.title,
+                episode_metadata.get_episode_display()
+            ))
+        ))
+    }
+}
+
+/// Replace the characters that are reserved on Windows or other platforms so the name can be used
+/// as a file name. Trailing dots and spaces are removed, since Windows drops them.
+fn sanitize_file_name(name: &str) -> String {
+    let name = name
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect::<String>();
+
+    match name.trim_end_matches(['.', ' ']) {
+        "" => "_".into(),
+        n => n.into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::sanitize_file_name;
+
+    #[test]
+    fn sanitize_file_name_replaces_reserved_characters() {
+        assert_eq!(
+            sanitize_file_name("Show: Re EP01 - The Storm/Part"),
+            "Show_ Re EP01 - The Storm_Part"
+        );
+        assert_eq!(sanitize_file_name(r#"a?b*c"d<e>f|g\h"#), "a_b_c_d_e_f_g_h");
+        assert_eq!(sanitize_file_name("Line\nBreak"), "Line_Break");
+    }
+
+    #[test]
+    fn sanitize_file_name_trims_trailing_dots_and_spaces() {
+        assert_eq!(sanitize_file_name("Season 2... "), "Season 2");
+        assert_eq!(sanitize_file_name(" . "), "_");
     }
 }
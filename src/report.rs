@@ -0,0 +1,84 @@
+use crate::{
+    error::Result,
+    metadata::{EpisodeMetadata, InvalidEpisode},
+};
+use serde::{Deserialize, Serialize};
+use std::{fs::write, path::Path};
+
+/// Summary of a compile run. Contains the outcome of every episode that was processed.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct CompileReport {
+    pub episodes: Vec<EpisodeReport>,
+    /// Episodes that couldn't be read, and weren't compiled.
+    #[serde(default)]
+    pub invalid_episodes: Vec<InvalidEpisode>,
+}
+
+/// Outcome of a single episode.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct EpisodeReport {
+    pub episode: EpisodeMetadata,
+    pub status: EpisodeStatus,
+}
+
+/// Whether an episode was compiled, skipped, or failed. Skipped and failed episodes carry the
+/// reason.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(tag = "status", content = "reason", rename_all = "snake_case")]
+pub enum EpisodeStatus {
+    Succeeded,
+    Skipped(String),
+    Failed(String),
+}
+
+impl CompileReport {
+    /// Add the outcome of an episode.
+    pub fn push(&mut self, episode: &EpisodeMetadata, status: EpisodeStatus) {
+        self.episodes.push(EpisodeReport {
+            episode: episode.clone(),
+            status,
+        })
+    }
+
+    /// Episodes that were compiled and packaged.
+    pub fn succeeded(&self) -> impl Iterator<Item = &EpisodeReport> {
+        self.episodes
+            .iter()
+            .filter(|e| matches!(e.status, EpisodeStatus::Succeeded))
+    }
+
+    /// Episodes that were not compiled on purpose.
+    pub fn skipped(&self) -> impl Iterator<Item = &EpisodeReport> {
+        self.episodes
+            .iter()
+            .filter(|e| matches!(e.status, EpisodeStatus::Skipped(_)))
+    }
+
+    /// Episodes that failed to compile or package.
+    pub fn failed(&self) -> impl Iterator<Item = &EpisodeReport> {
+        self.episodes
+            .iter()
+            .filter(|e| matches!(e.status, EpisodeStatus::Failed(_)))
+    }
+
+    /// Returns `true` if any episode failed or couldn't be read.
+    pub fn has_failures(&self) -> bool {
+        self.failed().next().is_some() || !self.invalid_episodes.is_empty()
+    }
+
+    /// Write the report as JSON.
+    pub fn save_json(&self, path: impl AsRef<Path>) -> Result<()> {
+        write(path, serde_json::to_string_pretty(self)?)?;
+
+        Ok(())
+    }
+}
+
+impl From<Result<EpisodeStatus>> for EpisodeStatus {
+    fn from(value: Result<EpisodeStatus>) -> Self {
+        match value {
+            Ok(s) => s,
+            Err(e) => EpisodeStatus::Failed(e.to_string()),
+        }
+    }
+}
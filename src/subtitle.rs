@@ -1,4 +1,7 @@
-use crate::{error::Result, metadata::EpisodeMetadata};
+use crate::{
+    error::{Error, Result},
+    metadata::EpisodeMetadata,
+};
 use rsubs_lib::{
     srt::{SRTFile, SRTLine},
     ssa::{SSAEvent, SSAFile, SSAStyle},
@@ -10,28 +13,43 @@ use rsubs_lib::{
     Subtitle,
 };
 use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, fs, path::Path};
+use std::{collections::HashMap, fmt, fs, path::Path, str::FromStr};
 
 macro_rules! new_ssa_subtitile {
-    ($value: expr) => {{
+    ($value: expr) => {
+        new_ssa_subtitile!($value, &SsaStyleConfig::default())
+    };
+    ($value: expr, $style: expr) => {{
+        let style: &SsaStyleConfig = $style;
+
         let mut ass_info = HashMap::new();
         ass_info.insert("Title".into(), "Bilibili Subtitle".into());
         ass_info.insert("ScriptType".into(), "v4.00+".into());
         ass_info.insert("WrapStyle".into(), "0".into());
         ass_info.insert("ScaledBorderAndShadow".into(), "yes".into());
         ass_info.insert("YCbCr Matrix".into(), "TV.601".into());
-        ass_info.insert("PlayResX".into(), "1920".into());
-        ass_info.insert("PlayResY".into(), "1080".into());
+        ass_info.insert("PlayResX".into(), style.play_res_x.to_string());
+        ass_info.insert("PlayResY".into(), style.play_res_y.to_string());
 
         let ass_styles = SSAStyle {
             name: "Default".into(),
-            fontsize: 70.,
-            bold: false, // For some reason `rsubs_lib` binds `false` with `-1` and true with `0`,
-            // but SSA format uses `-1` for `true` and `0` with false.
+            fontname: style.font_name.clone(),
+            fontsize: style.font_size,
+            firstcolor: ColorType::SSAColor(style.primary_color.into()),
+            secondcolor: ColorType::SSAColor(style.secondary_color.into()),
+            outlinecolor: ColorType::SSAColor(style.outline_color.into()),
+            backgroundcolor: ColorType::SSAColor(style.back_color.into()),
+            // For some reason `rsubs_lib` binds `false` with `-1` and true with `0`, but SSA
+            // format uses `-1` for `true` and `0` with false.
+            bold: !style.bold,
+            italic: !style.italic,
             borderstyle: 1,
-            outline: 5.,
-            alignment: Alignment::BottomCenter,
-            vmargin: 30,
+            outline: style.outline,
+            shadow: style.shadow,
+            alignment: style.alignment,
+            lmargin: style.margin_l,
+            rmargin: style.margin_r,
+            vmargin: style.margin_v,
             ..Default::default()
         };
 
@@ -40,14 +58,8 @@ macro_rules! new_ssa_subtitile {
         $value.body.iter().for_each(|b| {
             ass_event.push(SSAEvent {
                 style: "Default".into(),
-                line_start: Time {
-                    ms: (b.from * 1000.) as u32,
-                    ..Default::default()
-                },
-                line_end: Time {
-                    ms: (b.to * 1000.) as u32,
-                    ..Default::default()
-                },
+                line_start: new_time(b.from),
+                line_end: new_time(b.to),
                 line_text: b.content.clone().replace("\n", "\\N"),
                 ..Default::default()
             })
@@ -100,6 +112,18 @@ pub enum SubtitleType {
     Soft,
 }
 
+/// What to do with an episode that has no subtitle.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub enum MissingSubtitlePolicy {
+    /// Fail the episode.
+    #[default]
+    Require,
+    /// Skip the episode without compiling it.
+    Skip,
+    /// Combine only the audio and video.
+    MuxWithout,
+}
+
 /// Format of the subtitle. Though Bilibili only uses SSA and JSON.
 #[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord, Default)]
 pub enum SubtitleFormat {
@@ -110,18 +134,232 @@ pub enum SubtitleFormat {
     Vtt,
 }
 
+/// Style of SSA subtitles converted from JSON subtitles. The default is what Bilibili's own
+/// player looks like on a 1080p video.
+///
+/// # Load a style
+///
+/// ```
+/// use bilibili_extractor_lib::subtitle::{JsonSubtitle, SsaStyleConfig};
+///
+/// let style: SsaStyleConfig = serde_json::from_str(
+///     r#"{
+///         "font_name": "Noto Sans",
+///         "font_size": 60,
+///         "primary_color": "&H0000FFFF",
+///         "alignment": "TopCenter"
+///     }"#,
+/// )
+/// .unwrap();
+///
+/// let ssa = JsonSubtitle { body: vec![] }.to_ssa_with_style(&style);
+///
+/// assert!(ssa.to_string().contains("Style: Default,Noto Sans,60,&H0000FFFF"));
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct SsaStyleConfig {
+    pub font_name: String,
+    pub font_size: f32,
+    pub primary_color: SsaColor,
+    pub secondary_color: SsaColor,
+    pub outline_color: SsaColor,
+    pub back_color: SsaColor,
+    pub bold: bool,
+    pub italic: bool,
+    /// Width of the outline in pixels.
+    pub outline: f32,
+    /// Depth of the shadow in pixels.
+    pub shadow: f32,
+    pub alignment: Alignment,
+    pub margin_l: i32,
+    pub margin_r: i32,
+    pub margin_v: i32,
+    /// Resolution the sizes and margins above are relative to.
+    pub play_res_x: u32,
+    pub play_res_y: u32,
+}
+
+impl Default for SsaStyleConfig {
+    fn default() -> Self {
+        Self {
+            font_name: SSAStyle::default().fontname,
+            font_size: 70.,
+            primary_color: SsaColor::new(255, 255, 255, 0),
+            secondary_color: SsaColor::new(0, 0, 0, 0),
+            outline_color: SsaColor::new(0, 0, 0, 0),
+            back_color: SsaColor::new(0, 0, 0, 0),
+            bold: true,
+            italic: false,
+            outline: 5.,
+            shadow: 1.,
+            alignment: Alignment::BottomCenter,
+            margin_l: 0,
+            margin_r: 0,
+            margin_v: 30,
+            play_res_x: 1920,
+            play_res_y: 1080,
+        }
+    }
+}
+
+impl SsaStyleConfig {
+    /// Load a style from a JSON file. Missing fields use the default style.
+    pub fn new_from_path(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let content = fs::read_to_string(path)
+            .map_err(|e| Error::InputError(format!("Cannot read style {}: {e}", path.display())))?;
+
+        let style: Self = serde_json::from_str(&content)
+            .map_err(|e| Error::InputError(format!("Invalid style {}: {e}", path.display())))?;
+        style.validate()?;
+
+        Ok(style)
+    }
+
+    /// Check that the style can be used, which needs a non-zero `play_res_x` and `play_res_y`.
+    ///
+    /// ```
+    /// use bilibili_extractor_lib::subtitle::SsaStyleConfig;
+    ///
+    /// let style: SsaStyleConfig = serde_json::from_str(r#"{ "play_res_y": 0 }"#).unwrap();
+    ///
+    /// assert!(SsaStyleConfig::default().validate().is_ok());
+    /// assert!(style.validate().is_err());
+    /// assert_eq!(style.scale_to_resolution(1280, 720), style);
+    /// ```
+    pub fn validate(&self) -> Result<()> {
+        match (self.play_res_x, self.play_res_y) {
+            (0, _) | (_, 0) => Err(Error::InputError(format!(
+                "The play resolution of a subtitle style must not be 0, but it is {}x{}",
+                self.play_res_x, self.play_res_y
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    /// Get the style for a video of the given resolution. Sizes are scaled so the subtitles look
+    /// the same as they would at `play_res_x` by `play_res_y`.
+    ///
+    /// ```
+    /// use bilibili_extractor_lib::subtitle::SsaStyleConfig;
+    ///
+    /// let style = SsaStyleConfig::default().scale_to_resolution(1280, 720);
+    ///
+    /// assert_eq!((style.play_res_x, style.play_res_y), (1280, 720));
+    /// assert_eq!(style.font_size, 47.);
+    /// assert_eq!(style.margin_v, 20);
+    /// ```
+    ///
+    /// The style is returned unchanged if either resolution is 0.
+    pub fn scale_to_resolution(&self, width: u32, height: u32) -> Self {
+        if self.play_res_x == 0 || self.play_res_y == 0 || width == 0 || height == 0 {
+            return self.clone();
+        }
+
+        let scale_x = width as f32 / self.play_res_x as f32;
+        let scale_y = height as f32 / self.play_res_y as f32;
+        // Use the smaller scale for sizes, so text still fits videos of another aspect ratio.
+        let scale = scale_x.min(scale_y);
+        // SSA files only keep whole pixels of borders, and a border shouldn't disappear.
+        let scale_border = |b: f32| match b > 0. {
+            true => (b * scale).round().max(1.),
+            false => 0.,
+        };
+
+        Self {
+            font_size: (self.font_size * scale).round(),
+            outline: scale_border(self.outline),
+            shadow: scale_border(self.shadow),
+            margin_l: (self.margin_l as f32 * scale_x).round() as i32,
+            margin_r: (self.margin_r as f32 * scale_x).round() as i32,
+            margin_v: (self.margin_v as f32 * scale_y).round() as i32,
+            play_res_x: width,
+            play_res_y: height,
+            ..self.clone()
+        }
+    }
+}
+
+/// Color of an SSA style. Written as `&HAABBGGRR`, where an alpha of `00` is opaque and `FF` is
+/// fully transparent. `&HBBGGRR` is also accepted.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(try_from = "String", into = "String")]
+pub struct SsaColor {
+    pub red: u8,
+    pub green: u8,
+    pub blue: u8,
+    pub alpha: u8,
+}
+
+impl SsaColor {
+    pub fn new(red: u8, green: u8, blue: u8, alpha: u8) -> Self {
+        Self {
+            red,
+            green,
+            blue,
+            alpha,
+        }
+    }
+}
+
+impl FromStr for SsaColor {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let trimmed = s.trim();
+        let hex = trimmed
+            .strip_prefix("&H")
+            .or_else(|| trimmed.strip_prefix("&h"))
+            .map(|h| h.strip_suffix('&').unwrap_or(h))
+            .filter(|h| matches!(h.len(), 6 | 8) && h.bytes().all(|b| b.is_ascii_hexdigit()))
+            .and_then(|h| u32::from_str_radix(h, 16).ok())
+            .ok_or_else(|| Error::SubtitleError(format!("Invalid SSA color: {s}")))?;
+        let [alpha, blue, green, red] = hex.to_be_bytes();
+
+        Ok(Self::new(red, green, blue, alpha))
+    }
+}
+
+impl fmt::Display for SsaColor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "&H{:02X}{:02X}{:02X}{:02X}",
+            self.alpha, self.blue, self.green, self.red
+        )
+    }
+}
+
+impl TryFrom<String> for SsaColor {
+    type Error = Error;
+
+    fn try_from(value: String) -> Result<Self> {
+        value.parse()
+    }
+}
+
+impl From<SsaColor> for String {
+    fn from(value: SsaColor) -> Self {
+        value.to_string()
+    }
+}
+
+impl From<SsaColor> for Color {
+    fn from(value: SsaColor) -> Self {
+        Color {
+            r: value.red,
+            g: value.green,
+            b: value.blue,
+            a: value.alpha,
+        }
+    }
+}
+
 impl JsonSubtitle {
     /// Fetch the json subtitle of an episode.
     pub fn new_from_episode(episode: &EpisodeMetadata, subtitle_language: &str) -> Result<Self> {
-        let subtitle_path = episode
-            .path
-            .join(subtitle_language)
-            .read_dir()?
-            .next()
-            .ok_or("Subtitle directory is empty.")??
-            .path();
-
-        Self::new_from_path(subtitle_path)
+        Self::new_from_path(episode.get_subtitle_path(subtitle_language)?)
     }
 
     /// Create a `JsonSubtitle` from path.
@@ -141,6 +379,19 @@ impl JsonSubtitle {
         self.into()
     }
 
+    /// Convert to `SSAFile` that uses the given font, e.g. one attached to the video.
+    pub fn to_ssa_with_font_name(self, font_name: impl Into<String>) -> SSAFile {
+        self.to_ssa_with_style(&SsaStyleConfig {
+            font_name: font_name.into(),
+            ..Default::default()
+        })
+    }
+
+    /// Convert to `SSAFile` that uses the given style.
+    pub fn to_ssa_with_style(self, style: &SsaStyleConfig) -> SSAFile {
+        new_ssa_subtitile!(self, style)
+    }
+
     /// Convert to `SRTFile`.
     pub fn to_srt(self) -> SRTFile {
         self.into()
@@ -174,14 +425,8 @@ impl From<JsonSubtitle> for SRTFile {
             srt_line.push(SRTLine {
                 line_number: (i + 1) as i32,
                 line_text: b.content.clone(),
-                line_start: Time {
-                    ms: (b.from * 1000.) as u32,
-                    ..Default::default()
-                },
-                line_end: Time {
-                    ms: (b.to * 1000.) as u32,
-                    ..Default::default()
-                },
+                line_start: new_time(b.from),
+                line_end: new_time(b.to),
             });
         }
 
@@ -216,14 +461,8 @@ impl From<JsonSubtitle> for VTTFile {
             vtt_lines.push(VTTLine {
                 line_number: i.to_string(),
                 style: Some("Default".into()),
-                line_start: Time {
-                    ms: (b.from * 1000.) as u32,
-                    ..Default::default()
-                },
-                line_end: Time {
-                    ms: (b.to * 1000.) as u32,
-                    ..Default::default()
-                },
+                line_start: new_time(b.from),
+                line_end: new_time(b.to),
                 position: None,
                 line_text: b.content.clone(),
             })
@@ -242,13 +481,12 @@ impl SubtitleFormat {
         episode: &EpisodeMetadata,
         subtitle_language: &str,
     ) -> Result<Self> {
-        let subtitle_path = episode
-            .path
-            .join(subtitle_language)
-            .read_dir()?
-            .next()
-            .ok_or("Subtitle directory is empty")??
-            .path();
+        Self::new_from_path(episode.get_subtitle_path(subtitle_language)?)
+    }
+
+    /// Get the subtitle format of a file from its extension.
+    pub fn new_from_path(subtitle_path: impl AsRef<Path>) -> Result<Self> {
+        let subtitle_path = subtitle_path.as_ref();
         let extension = subtitle_path.extension().ok_or(format!(
             "Subtitle {} has no extension.",
             subtitle_path.display()
@@ -266,3 +504,190 @@ impl SubtitleFormat {
         }
     }
 }
+
+/// Convert a Bilibili subtitle language, e.g. `zh-Hans`, to its ISO 639-2 code used by Matroska.
+/// Unknown languages are returned as is.
+pub fn get_iso_639_2_language(language: &str) -> &str {
+    match language.split(['-', '_']).next().unwrap_or(language) {
+        "en" => "eng",
+        "zh" => "chi",
+        "ja" => "jpn",
+        "ko" => "kor",
+        "th" => "tha",
+        "id" => "ind",
+        "vi" => "vie",
+        "ms" => "may",
+        "es" => "spa",
+        "pt" => "por",
+        "fr" => "fre",
+        "de" => "ger",
+        "ar" => "ara",
+        "ru" => "rus",
+        _ => language,
+    }
+}
+
+/// Get a human readable name of a Bilibili subtitle language. Used as the track title. Unknown
+/// languages are returned as is.
+pub fn get_language_name(language: &str) -> &str {
+    match language {
+        "en" => "English",
+        "zh-Hans" | "zh-CN" => "简体中文",
+        "zh-Hant" | "zh-TW" | "zh-HK" => "繁體中文",
+        "ja" => "日本語",
+        "ko" => "한국어",
+        "th" => "ไทย",
+        "id" => "Bahasa Indonesia",
+        "vi" => "Tiếng Việt",
+        "ms" => "Bahasa Melayu",
+        "es" => "Español",
+        "pt" => "Português",
+        "fr" => "Français",
+        "de" => "Deutsch",
+        "ar" => "العربية",
+        "ru" => "Русский",
+        _ => language,
+    }
+}
+
+/// Create a `Time` from seconds. `rsubs_lib` doesn't carry milliseconds over to seconds, minutes,
+/// and hours when writing, so every field is set.
+pub(crate) fn new_time(seconds: f32) -> Time {
+    let ms = (seconds.max(0.) * 1000.).round() as u32;
+
+    Time {
+        h: ms / 3_600_000,
+        m: ms / 60_000 % 60,
+        s: ms / 1000 % 60,
+        ms: ms % 1000,
+        ..Default::default()
+    }
+}
+
+/// Set the `Fontname` of the styles in an SSA file to `font_name`, e.g. to use a font attached to
+/// the video. Styles already using one of `available_fonts` are kept. Line endings are kept as
+/// they are.
+pub fn set_ssa_font_name(
+    path: impl AsRef<Path>,
+    font_name: &str,
+    available_fonts: &[String],
+) -> Result<()> {
+    let content = fs::read_to_string(&path)?;
+    let mut fontname_index = None;
+    // `@` marks a vertical font, which is still the same family.
+    let is_available = |f: &str| {
+        let f = f.trim().trim_start_matches('@');
+        available_fonts.iter().any(|a| a.eq_ignore_ascii_case(f))
+    };
+
+    let content: String = content
+        .split_inclusive('\n')
+        .map(|l| {
+            let line = l.trim_end_matches(['\r', '\n']);
+            let line_ending = &l[line.len()..];
+
+            if line.starts_with('[') {
+                fontname_index = None;
+            } else if let Some(format) = line.strip_prefix("Format:") {
+                fontname_index = format
+                    .split(',')
+                    .position(|f| f.trim().eq_ignore_ascii_case("Fontname"));
+            } else if let (Some(style), Some(i)) = (line.strip_prefix("Style:"), fontname_index) {
+                let mut fields: Vec<&str> = style.split(',').collect();
+
+                match fields.get_mut(i) {
+                    Some(f) if !is_available(f) => {
+                        *f = font_name;
+                        return format!("Style: {}{line_ending}", fields.join(",").trim_start());
+                    }
+                    _ => {}
+                }
+            }
+
+            l.to_string()
+        })
+        .collect();
+
+    fs::write(path, content)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mp4::tests::get_temporary_path;
+
+    #[test]
+    fn set_ssa_font_name_only_changes_fonts_that_are_not_available() {
+        let path = get_temporary_path("set_ssa_font_name.ass");
+        fs::write(
+            &path,
+            [
+                "[Script Info]",
+                "Title: Fonts",
+                "",
+                "[V4+ Styles]",
+                "Format: Name, Fontname, Fontsize",
+                "Style: Default,Arial,60",
+                "Style: Thai,noto sans thai,60",
+                "Style: Vertical,@Noto Sans CJK SC,60",
+                "",
+                "[Events]",
+                "Format: Layer, Start, End, Style, Text",
+                "Dialogue: 0,0:00:00.00,0:00:01.00,Default,Arial",
+                "",
+            ]
+            .join("\r\n"),
+        )
+        .unwrap();
+
+        set_ssa_font_name(
+            &path,
+            "Noto Sans CJK SC",
+            &["Noto Sans CJK SC".into(), "Noto Sans Thai".into()],
+        )
+        .unwrap();
+        let content = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(
+            content,
+            [
+                "[Script Info]",
+                "Title: Fonts",
+                "",
+                "[V4+ Styles]",
+                "Format: Name, Fontname, Fontsize",
+                "Style: Default,Noto Sans CJK SC,60",
+                "Style: Thai,noto sans thai,60",
+                "Style: Vertical,@Noto Sans CJK SC,60",
+                "",
+                "[Events]",
+                "Format: Layer, Start, End, Style, Text",
+                "Dialogue: 0,0:00:00.00,0:00:01.00,Default,Arial",
+                "",
+            ]
+            .join("\r\n")
+        );
+    }
+
+    #[test]
+    fn set_ssa_font_name_uses_the_fontname_column_of_the_format() {
+        let path = get_temporary_path("set_ssa_font_name_format.ass");
+        fs::write(
+            &path,
+            "[V4+ Styles]\nFormat: Fontsize, Name, Fontname\nStyle: 60,Default,Arial",
+        )
+        .unwrap();
+
+        set_ssa_font_name(&path, "Noto Sans", &[]).unwrap();
+        let content = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(
+            content,
+            "[V4+ Styles]\nFormat: Fontsize, Name, Fontname\nStyle: 60,Default,Noto Sans"
+        );
+    }
+}